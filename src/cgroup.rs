@@ -0,0 +1,322 @@
+//! Linux cgroup v2 containment of child processes
+//!
+//! A child is moved into its cgroup right before it executes the program,
+//! by writing to the `cgroup.procs` file of the cgroup. The file is opened
+//! by the parent before spawning, so the child only has to do a `write`,
+//! which is safe to call between `fork` and `exec`. This way all the
+//! processes the child spawns are contained in the cgroup from the start.
+//!
+//! The cgroup is killed through `cgroup.kill` and removed once the last
+//! handle to it is dropped.
+//!
+//! Controllers can only be enabled for the children of a cgroup that has no
+//! processes in it. The cgroup of the current process contains the process
+//! itself, so limits can usually only be set on cgroups created under a
+//! parent delegated for the children alone. The current process is never
+//! moved to make room for them.
+//!
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use may::coroutine;
+
+use crate::imp::{ArmedFd, PreExecFd};
+
+/// A cgroup v2 directory that child processes can be placed into.
+///
+/// A `Cgroup` is a cheap handle that can be cloned and shared by several
+/// [`Command`]s, so a named group of children can be managed as a whole.
+/// Once the last handle is dropped all the processes in the cgroup are
+/// killed and the directory is removed.
+///
+/// This type is only available with the `cgroup` feature on Linux, and
+/// requires a cgroup v2 hierarchy that is delegated to the current user.
+///
+/// # Examples
+///
+/// ```no_run
+/// use may_process::{Cgroup, Command};
+///
+/// let group = Cgroup::new("build").expect("can't create cgroup");
+/// group.set_memory_max(Some(1 << 30)).unwrap();
+/// group.set_pids_max(Some(64)).unwrap();
+///
+/// let status = Command::new("make")
+///         .cgroup(&group)
+///         .status()
+///         .expect("make command failed to start");
+/// ```
+///
+/// [`Command`]: struct.Command.html
+#[derive(Clone)]
+pub struct Cgroup {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    path: PathBuf,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let _ = write_file(&self.path.join("cgroup.kill"), "1");
+        // the cgroup can only be removed after all the processes are gone
+        let path = self.path.clone();
+        may::go!(move || {
+            let start = Instant::now();
+            while fs::remove_dir(&path).is_err() && start.elapsed() < REMOVE_TIMEOUT {
+                coroutine::sleep(Duration::from_millis(10));
+            }
+        });
+    }
+}
+
+// how long we try to remove a killed cgroup
+const REMOVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl fmt::Debug for Cgroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cgroup")
+            .field("path", &self.inner.path)
+            .finish()
+    }
+}
+
+impl Cgroup {
+    /// Creates a new cgroup named `name` under the cgroup of the current
+    /// process.
+    ///
+    /// The controllers are enabled in the cgroup of the current process as
+    /// with [`new_in`]. That fails if the cgroup has processes in it, which
+    /// includes the current process itself, so this only works if the
+    /// current process is in the root cgroup of a delegated hierarchy, e.g.
+    /// in a container. Otherwise use [`new_in`] with a delegated cgroup that
+    /// is kept for the children.
+    ///
+    /// [`new_in`]: #method.new_in
+    pub fn new(name: &str) -> io::Result<Cgroup> {
+        let parent = current()?;
+        enable_controllers(&parent).map_err(|e| {
+            let msg = format!("{}, use Cgroup::new_in with a delegated cgroup", e);
+            io::Error::new(e.kind(), msg)
+        })?;
+        Cgroup::create(&parent, name)
+    }
+
+    /// Creates a new cgroup named `name` under the cgroup directory `parent`.
+    ///
+    /// The memory, cpu and pids controllers available in `parent` are
+    /// enabled for its children, so that the limits can be set on the new
+    /// cgroup. An error is returned if they can't be enabled, which is the
+    /// case if `parent` has processes in it, so `parent` should be a cgroup
+    /// delegated for the children alone.
+    pub fn new_in<P: AsRef<Path>>(parent: P, name: &str) -> io::Result<Cgroup> {
+        let parent = parent.as_ref();
+        enable_controllers(parent)?;
+        Cgroup::create(parent, name)
+    }
+
+    fn create(parent: &Path, name: &str) -> io::Result<Cgroup> {
+        let path = parent.join(name);
+        fs::create_dir(&path)?;
+        Ok(Cgroup {
+            inner: Arc::new(Inner { path }),
+        })
+    }
+
+    /// Returns the path of the cgroup directory.
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    /// Sets `memory.max`, the hard limit of the memory usage in bytes.
+    /// `None` removes the limit.
+    pub fn set_memory_max(&self, bytes: Option<u64>) -> io::Result<()> {
+        self.write("memory.max", &limit(bytes))
+    }
+
+    /// Sets `cpu.max`, allowing the processes to run for `quota` in each
+    /// `period`. A `quota` of `None` removes the limit.
+    pub fn set_cpu_max(&self, quota: Option<Duration>, period: Duration) -> io::Result<()> {
+        let quota = limit(quota.map(|q| q.as_micros() as u64));
+        let value = format!("{} {}", quota, period.as_micros());
+        self.write("cpu.max", &value)
+    }
+
+    /// Sets `pids.max`, the maximum number of processes in the cgroup.
+    /// `None` removes the limit.
+    pub fn set_pids_max(&self, max: Option<u64>) -> io::Result<()> {
+        self.write("pids.max", &limit(max))
+    }
+
+    /// Returns the ids of all the processes in the cgroup.
+    pub fn procs(&self) -> io::Result<Vec<u32>> {
+        let procs = fs::read_to_string(self.inner.path.join("cgroup.procs"))?;
+        Ok(procs.lines().filter_map(|p| p.parse().ok()).collect())
+    }
+
+    /// Kills all the processes in the cgroup through `cgroup.kill`,
+    /// which is available since Linux 5.14.
+    pub fn kill(&self) -> io::Result<()> {
+        self.write("cgroup.kill", "1")
+    }
+
+    fn write(&self, file: &str, value: &str) -> io::Result<()> {
+        write_file(&self.inner.path.join(file), value).map_err(|e| {
+            if e.kind() != io::ErrorKind::NotFound {
+                return e;
+            }
+            let msg = format!("{} is not available, its controller is not enabled", file);
+            io::Error::new(e.kind(), msg)
+        })
+    }
+}
+
+fn limit(value: Option<u64>) -> String {
+    value.map_or_else(|| "max".to_owned(), |v| v.to_string())
+}
+
+fn write_file(path: &Path, value: &str) -> io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .write_all(value.as_bytes())
+}
+
+// enable the controllers that we set limits for, the ones that are not
+// available in `parent` are skipped
+fn enable_controllers(parent: &Path) -> io::Result<()> {
+    try_enable_controllers(parent).map_err(|e| {
+        let msg = format!(
+            "can't enable the controllers in {}: {}",
+            parent.display(),
+            e
+        );
+        io::Error::new(e.kind(), msg)
+    })
+}
+
+fn try_enable_controllers(parent: &Path) -> io::Result<()> {
+    let available = fs::read_to_string(parent.join("cgroup.controllers"))?;
+    let enabled = fs::read_to_string(parent.join("cgroup.subtree_control"))?;
+    let control = parent.join("cgroup.subtree_control");
+    for c in &["memory", "cpu", "pids"] {
+        let has = |s: &str| s.split_whitespace().any(|x| x == *c);
+        if has(&available) && !has(&enabled) {
+            write_file(&control, &format!("+{}", c))?;
+        }
+    }
+    Ok(())
+}
+
+// the cgroup directory of the current process
+fn current() -> io::Result<PathBuf> {
+    let not_found = || io::Error::new(io::ErrorKind::NotFound, "cgroup v2 is not available");
+
+    let cgroup = fs::read_to_string("/proc/self/cgroup")?;
+    let path = cgroup
+        .lines()
+        .find_map(|l| l.strip_prefix("0::"))
+        .ok_or_else(not_found)?;
+
+    // the mount point is the 5th field, the fs type follows the separator
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    let mount = mountinfo
+        .lines()
+        .filter_map(|l| {
+            let mut parts = l.splitn(2, " - ");
+            let fields = parts.next()?;
+            let fs_type = parts.next()?.split_whitespace().next()?;
+            if fs_type == "cgroup2" {
+                fields.split_whitespace().nth(4)
+            } else {
+                None
+            }
+        })
+        .next()
+        .ok_or_else(not_found)?;
+
+    Ok(Path::new(mount).join(path.trim_start_matches('/')))
+}
+
+enum Target {
+    Join(Cgroup),
+    Fresh,
+}
+
+// how the children of a command are placed into cgroups
+pub(crate) struct Placement {
+    target: Target,
+    // the `cgroup.procs` file of the next child
+    procs: PreExecFd,
+}
+
+// keeps the `cgroup.procs` file open until the child is spawned
+pub(crate) struct Prepared {
+    cgroup: Cgroup,
+    // dropped before the file is closed
+    _armed: ArmedFd,
+    _file: File,
+}
+
+impl Prepared {
+    pub(crate) fn into_cgroup(self) -> Cgroup {
+        self.cgroup
+    }
+}
+
+impl Placement {
+    // set the cgroup placement of a command
+    pub(crate) fn set(
+        placement: &mut Option<Placement>,
+        cmd: &mut process::Command,
+        group: Option<&Cgroup>,
+    ) {
+        let target = match group {
+            Some(group) => Target::Join(group.clone()),
+            None => Target::Fresh,
+        };
+        if let Some(ref mut p) = *placement {
+            p.target = target;
+            return;
+        }
+
+        let procs = unsafe {
+            PreExecFd::new(cmd, -1, |fd| {
+                libc::write(fd, b"0".as_ptr() as *const _, 1) as libc::c_int
+            })
+        };
+        *placement = Some(Placement { target, procs });
+    }
+
+    // get the cgroup ready for the next child
+    pub(crate) fn prepare(&self) -> io::Result<Prepared> {
+        static SEQ: AtomicUsize = AtomicUsize::new(0);
+
+        let cgroup = match self.target {
+            Target::Join(ref group) => group.clone(),
+            Target::Fresh => {
+                // no limits are set on it, so no controllers are needed
+                let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+                let name = format!("may_process-{}-{}", process::id(), seq);
+                Cgroup::create(&current()?, &name)?
+            }
+        };
+        let file = OpenOptions::new()
+            .write(true)
+            .open(cgroup.path().join("cgroup.procs"))?;
+        Ok(Prepared {
+            cgroup,
+            _armed: self.procs.arm(file.as_raw_fd()),
+            _file: file,
+        })
+    }
+}
@@ -0,0 +1,144 @@
+//! The error of running a command that is expected to succeed
+//!
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+
+use crate::Command;
+
+// how much of the captured stderr is kept in the error
+const STDERR_TAIL: usize = 4096;
+
+/// An error returned by the checked runners of [`Command`], such as
+/// [`checked_output`], if the command can't be run or doesn't exit
+/// successfully.
+///
+/// It records the command line, the exit status and the tail of the
+/// captured stderr, so the error message tells what went wrong without
+/// further context. It converts into an `io::Error` so it can be used with
+/// `?` in functions returning `io::Result`.
+///
+/// [`Command`]: struct.Command.html
+/// [`checked_output`]: struct.Command.html#method.checked_output
+#[derive(Debug)]
+pub struct Error {
+    program: String,
+    args: Vec<String>,
+    kind: Kind,
+    stderr: Vec<u8>,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Io(io::Error),
+    Status(ExitStatus),
+}
+
+impl Error {
+    pub(crate) fn io(cmd: &Command, err: io::Error) -> Error {
+        Error::new(cmd, Kind::Io(err), &[])
+    }
+
+    pub(crate) fn failed(cmd: &Command, status: ExitStatus, stderr: &[u8]) -> Error {
+        Error::new(cmd, Kind::Status(status), stderr)
+    }
+
+    fn new(cmd: &Command, kind: Kind, stderr: &[u8]) -> Error {
+        let lossy = |s: &std::ffi::OsStr| s.to_string_lossy().into_owned();
+        let tail = stderr.len().saturating_sub(STDERR_TAIL);
+        Error {
+            program: lossy(cmd.inner.get_program()),
+            args: cmd.inner.get_args().map(lossy).collect(),
+            kind,
+            stderr: stderr[tail..].to_vec(),
+        }
+    }
+
+    /// Returns the program of the command, non-utf8 data is loosely
+    /// converted.
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    /// Returns the arguments of the command, non-utf8 data is loosely
+    /// converted.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Returns the exit status of the child, if it ran to completion.
+    pub fn status(&self) -> Option<ExitStatus> {
+        match self.kind {
+            Kind::Status(status) => Some(status),
+            Kind::Io(_) => None,
+        }
+    }
+
+    /// Returns the exit code of the child, if it exited normally.
+    pub fn code(&self) -> Option<i32> {
+        self.status().and_then(|s| s.code())
+    }
+
+    /// Returns the signal that terminated the child, if it was killed by a
+    /// signal.
+    #[cfg(unix)]
+    pub fn signal(&self) -> Option<i32> {
+        use std::os::unix::process::ExitStatusExt;
+        self.status().and_then(|s| s.signal())
+    }
+
+    /// Returns the last part of the stderr of the child, if it was captured.
+    ///
+    /// At most 4 KiB of data is kept.
+    pub fn stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+
+    /// Returns the underlying I/O error, if the command failed to run.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self.kind {
+            Kind::Io(ref e) => Some(e),
+            Kind::Status(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        match self.kind {
+            Kind::Io(ref e) => write!(f, "` failed to run: {}", e)?,
+            Kind::Status(status) => write!(f, "` failed with {}", status)?,
+        }
+        let stderr = String::from_utf8_lossy(&self.stderr);
+        let stderr = stderr.trim_end();
+        if !stderr.is_empty() {
+            write!(f, "\nstderr:\n{}", stderr)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self.kind {
+            Kind::Io(ref e) => Some(e),
+            Kind::Status(_) => None,
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err.kind {
+            Kind::Io(ref e) => e.kind(),
+            Kind::Status(_) => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
@@ -0,0 +1,277 @@
+//! Expect-style automation of interactive programs
+//!
+//! The output of the child is read in its own coroutine and sent to a
+//! channel in chunks. Each `expect` call takes chunks from the channel into
+//! a buffer until the pattern is found in it or the timeout expires, so a
+//! slow or stuck program never blocks the worker thread.
+//!
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use may::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use regex::bytes::Regex;
+
+use crate::stdio::read_chunks;
+use crate::{Child, Command};
+
+/// A pattern to look for in the output of a [`Session`].
+///
+/// A pattern is either a literal string, which is converted from `&str` or
+/// `String`, or a regular expression, which is converted from a
+/// `regex::bytes::Regex` since the output may not be utf8.
+///
+/// [`Session`]: struct.Session.html
+#[derive(Clone, Debug)]
+pub struct Pattern(Kind);
+
+#[derive(Clone, Debug)]
+enum Kind {
+    Literal(Vec<u8>),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Creates a pattern matching the literal `text`.
+    pub fn literal<T: AsRef<[u8]>>(text: T) -> Pattern {
+        Pattern(Kind::Literal(text.as_ref().to_vec()))
+    }
+
+    /// Creates a pattern matching the regular expression `re`.
+    pub fn regex(re: &str) -> Result<Pattern, regex::Error> {
+        Regex::new(re).map(|re| Pattern(Kind::Regex(re)))
+    }
+
+    // the range of the first match in `data`
+    fn find(&self, data: &[u8]) -> Option<(usize, usize)> {
+        match self.0 {
+            Kind::Literal(ref text) if text.is_empty() => Some((0, 0)),
+            Kind::Literal(ref text) => data
+                .windows(text.len())
+                .position(|w| w == &text[..])
+                .map(|i| (i, i + text.len())),
+            Kind::Regex(ref re) => re.find(data).map(|m| (m.start(), m.end())),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Pattern {
+    fn from(text: &'a str) -> Pattern {
+        Pattern::literal(text)
+    }
+}
+
+impl From<String> for Pattern {
+    fn from(text: String) -> Pattern {
+        Pattern::literal(text)
+    }
+}
+
+impl From<Regex> for Pattern {
+    fn from(re: Regex) -> Pattern {
+        Pattern(Kind::Regex(re))
+    }
+}
+
+/// The result of a successful [`Session::expect`].
+///
+/// [`Session::expect`]: struct.Session.html#method.expect
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    /// The output of the child since the previous match, before the text
+    /// matching the pattern.
+    pub before: Vec<u8>,
+    /// The text matching the pattern.
+    pub matched: Vec<u8>,
+}
+
+/// An interactive session with a child process, in the manner of the
+/// `expect` tool.
+///
+/// The session writes to the stdin of the child and reads what it writes to
+/// stdout. If the command is configured with [`Command::pty`], the session
+/// talks to the child through its pseudo-terminal instead, which makes
+/// programs that insist on a terminal, such as password prompts, work.
+///
+/// This type is only available with the `expect` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// use may_process::{Command, Pattern, Session};
+/// use std::time::Duration;
+///
+/// let mut cmd = Command::new("python3");
+/// cmd.arg("-i");
+///
+/// let timeout = Duration::from_secs(5);
+/// let mut session = Session::spawn(cmd).expect("failed to start python");
+/// session.expect(">>> ", timeout).unwrap();
+/// session.send_line("print(6 * 7)").unwrap();
+/// let m = session.expect(Pattern::regex(r"\d+").unwrap(), timeout).unwrap();
+/// assert_eq!(m.matched, b"42");
+/// ```
+///
+/// [`Command::pty`]: struct.Command.html#method.pty
+pub struct Session {
+    child: Child,
+    writer: Option<Writer>,
+    pty: bool,
+    output: Receiver<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl Session {
+    /// Spawns `cmd` as a child process and starts a session with it.
+    ///
+    /// The stdin and stdout of the command are replaced with pipes unless
+    /// it's spawned on a pseudo-terminal. Its stderr is left as configured,
+    /// use [`Command::stderr_to_stdout`] to see it in the session as well.
+    ///
+    /// [`Command::stderr_to_stdout`]: struct.Command.html#method.stderr_to_stdout
+    pub fn spawn(mut cmd: Command) -> io::Result<Session> {
+        // the pty takes over all the stdio if the command has one
+        cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+        let mut child = cmd.spawn()?;
+
+        let (reader, writer, pty) = split(&mut child)?;
+        let (tx, rx) = channel();
+        if let Some(reader) = reader {
+            read_chunks(reader, tx, |data| data);
+        }
+
+        Ok(Session {
+            child,
+            writer,
+            pty,
+            output: rx,
+            buf: Vec::new(),
+            eof: false,
+        })
+    }
+
+    /// Returns the child process of the session.
+    pub fn child(&mut self) -> &mut Child {
+        &mut self.child
+    }
+
+    /// Waits at most `timeout` for the child to write text matching
+    /// `pattern`, returning the matched text and the output before it.
+    ///
+    /// The output up to the end of the match is consumed, the next call
+    /// looks for its pattern in the output after it.
+    ///
+    /// An error of kind `TimedOut` is returned if the pattern is not found
+    /// in time, and an error of kind `UnexpectedEof` if the child closes
+    /// its output before writing it. The output read so far is kept for
+    /// the next call in both cases.
+    pub fn expect<P: Into<Pattern>>(&mut self, pattern: P, timeout: Duration) -> io::Result<Match> {
+        let pattern = pattern.into();
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some((start, end)) = pattern.find(&self.buf) {
+                let mut before: Vec<u8> = self.buf.drain(..end).collect();
+                let matched = before.split_off(start);
+                return Ok(Match { before, matched });
+            }
+            if !self.fill(deadline)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "output closed before the pattern is found",
+                ));
+            }
+        }
+    }
+
+    /// Waits at most `timeout` for the child to close its output, returning
+    /// all the output that is not consumed yet.
+    pub fn expect_eof(&mut self, timeout: Duration) -> io::Result<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        while self.fill(deadline)? {}
+        Ok(std::mem::take(&mut self.buf))
+    }
+
+    /// Writes `data` to the child.
+    pub fn send<T: AsRef<[u8]>>(&mut self, data: T) -> io::Result<()> {
+        let writer = self.writer.as_mut().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "stdin of the child is closed")
+        })?;
+        writer.write_all(data.as_ref())?;
+        writer.flush()
+    }
+
+    /// Writes `line` followed by a newline to the child.
+    pub fn send_line(&mut self, line: &str) -> io::Result<()> {
+        self.send(format!("{}\n", line))
+    }
+
+    /// Closes the stdin of the child, so it reads the end of the input.
+    ///
+    /// On a pseudo-terminal this sends the end-of-file character instead,
+    /// which only takes effect at the beginning of a line.
+    pub fn send_eof(&mut self) -> io::Result<()> {
+        if self.pty {
+            // ^D
+            return self.send([4]);
+        }
+        self.writer = None;
+        Ok(())
+    }
+
+    /// Closes the stdin of the child and waits for it to exit.
+    pub fn wait(mut self) -> io::Result<ExitStatus> {
+        self.writer = None;
+        self.child.wait()
+    }
+
+    // wait for the next chunk of output, returns `false` on the end of it
+    fn fill(&mut self, deadline: Instant) -> io::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.output.recv_timeout(timeout) {
+            Ok(chunk) => {
+                self.buf.extend_from_slice(&chunk?);
+                Ok(true)
+            }
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "pattern not found in time",
+            )),
+            Err(RecvTimeoutError::Disconnected) => {
+                self.eof = true;
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("child", &self.child)
+            .field("buffered", &self.buf.len())
+            .finish()
+    }
+}
+
+type Reader = Box<dyn Read + Send>;
+type Writer = Box<dyn Write + Send>;
+
+// the output and the input of the child, on its pty if it has one
+fn split(child: &mut Child) -> io::Result<(Option<Reader>, Option<Writer>, bool)> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(pty) = child.take_pty() {
+            let writer = pty.try_clone()?;
+            return Ok((Some(Box::new(pty)), Some(Box::new(writer)), true));
+        }
+    }
+    let reader = child.take_stdout().map(|r| Box::new(r) as Reader);
+    let writer = child.take_stdin().map(|w| Box::new(w) as Writer);
+    Ok((reader, writer, false))
+}
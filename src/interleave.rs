@@ -0,0 +1,178 @@
+//! Interleaved capture of stdout and stderr
+//!
+//! Both pipes are read in their own coroutines, which stamp every chunk
+//! of data with the time it was read and send it to a shared channel. The
+//! chunks keep the order in which the child wrote them as closely as the
+//! scheduling of the readers allows.
+//!
+//! An output limit applies to the total of the chunks of both streams, as
+//! they are interleaved into one log.
+//!
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Read};
+use std::process::ExitStatus;
+use std::time::Instant;
+
+use may::sync::mpsc::channel;
+
+use crate::limit::{OutputLimit, Overflow};
+use crate::stdio::read_chunks;
+
+/// One of the output streams of a child process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Stream {
+    /// The standard output.
+    Stdout,
+    /// The standard error.
+    Stderr,
+}
+
+/// The output of a finished process with stdout and stderr interleaved.
+///
+/// This is returned by [`Command::output_interleaved`].
+///
+/// [`Command::output_interleaved`]: struct.Command.html#method.output_interleaved
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterleavedOutput {
+    /// The status (exit code) of the process.
+    pub status: ExitStatus,
+    /// The chunks of data read from the streams, in the order they were
+    /// read, each with the time it was read.
+    pub chunks: Vec<(Stream, Instant, Vec<u8>)>,
+    /// Whether the total of the chunks exceeded the limit set by
+    /// [`Command::max_output`] and is truncated.
+    ///
+    /// [`Command::max_output`]: struct.Command.html#method.max_output
+    pub truncated: bool,
+}
+
+impl InterleavedOutput {
+    /// Returns the data of one stream alone.
+    pub fn stream(&self, stream: Stream) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|c| c.0 == stream)
+            .flat_map(|c| c.2.iter().copied())
+            .collect()
+    }
+
+    /// Returns the data of both streams combined, as if stderr was
+    /// redirected to stdout.
+    pub fn combined(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .flat_map(|c| c.2.iter().copied())
+            .collect()
+    }
+
+    /// Renders the output as a combined log, where each line is prefixed
+    /// with the time since the first chunk and the stream it comes from.
+    ///
+    /// ```text
+    ///   0.000000 out | compiling foo
+    ///   0.001234 err | warning: unused variable
+    /// ```
+    ///
+    /// A line written by the child in several parts is shown as several
+    /// lines. Non-utf8 data is loosely converted.
+    pub fn render(&self) -> String {
+        let start = match self.chunks.first() {
+            Some(c) => c.1,
+            None => return String::new(),
+        };
+        let mut log = String::new();
+        for (stream, time, data) in &self.chunks {
+            let secs = time.duration_since(start).as_secs_f64();
+            let name = match stream {
+                Stream::Stdout => "out",
+                Stream::Stderr => "err",
+            };
+            for line in String::from_utf8_lossy(data).lines() {
+                let _ = writeln!(log, "{:>10.6} {} | {}", secs, name, line);
+            }
+        }
+        log
+    }
+}
+
+type Chunk = (Stream, Instant, Vec<u8>);
+
+// collect the chunks of both pipes until they are closed, keeping at most
+// `limit` bytes in total, `kill` is called once the limit is exceeded if
+// the policy says so
+pub(crate) fn collect<O, E, K>(
+    stdout: Option<O>,
+    stderr: Option<E>,
+    limit: Option<OutputLimit>,
+    mut kill: K,
+) -> io::Result<(Vec<Chunk>, bool)>
+where
+    O: Read + Send + 'static,
+    E: Read + Send + 'static,
+    K: FnMut() -> io::Result<()>,
+{
+    let (tx, rx) = channel();
+    let stamp = |stream| move |data| (stream, Instant::now(), data);
+    if let Some(io) = stdout {
+        read_chunks(io, tx.clone(), stamp(Stream::Stdout));
+    }
+    if let Some(io) = stderr {
+        read_chunks(io, tx.clone(), stamp(Stream::Stderr));
+    }
+    drop(tx);
+
+    let mut chunks = VecDeque::new();
+    let mut kept = 0;
+    let mut truncated = false;
+    // the channel is closed once both readers are done
+    for chunk in rx.iter() {
+        let mut chunk = chunk?;
+        let limit = match limit {
+            Some(limit) if kept + chunk.2.len() > limit.max => limit,
+            _ => {
+                kept += chunk.2.len();
+                chunks.push_back(chunk);
+                continue;
+            }
+        };
+
+        let first = !truncated;
+        truncated = true;
+        if limit.overflow == Overflow::KeepTail {
+            kept += chunk.2.len();
+            chunks.push_back(chunk);
+            while kept > limit.max {
+                let excess = kept - limit.max;
+                let front = chunks.front_mut().expect("no chunk");
+                if front.2.len() <= excess {
+                    kept -= front.2.len();
+                    chunks.pop_front();
+                } else {
+                    front.2.drain(..excess);
+                    kept -= excess;
+                }
+            }
+            continue;
+        }
+
+        let room = limit.max - kept;
+        if room > 0 {
+            chunk.2.truncate(room);
+            kept += room;
+            chunks.push_back(chunk);
+        }
+        if first && limit.kills() {
+            kill()?;
+            if limit.overflow == Overflow::Error {
+                return Err(io::Error::other("process output exceeds the limit"));
+            }
+        }
+    }
+
+    let mut chunks = Vec::from(chunks);
+    // the readers may send their chunks slightly out of order
+    chunks.sort_by_key(|c| c.1);
+    Ok((chunks, truncated))
+}
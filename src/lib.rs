@@ -38,6 +38,54 @@ use std::fmt;
 use std::io::{self, Read};
 use std::path::Path;
 use std::process::{self, ExitStatus, Output, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use may::coroutine::JoinHandle;
+use may::sync::mpsc::{channel, RecvTimeoutError};
+
+use crate::limit::OutputLimit;
+
+#[cfg(all(target_os = "linux", feature = "cgroup"))]
+mod cgroup;
+mod error;
+#[cfg(feature = "expect")]
+mod expect;
+mod interleave;
+mod limit;
+mod pipeline;
+mod pool;
+#[cfg(target_os = "linux")]
+mod pty;
+#[cfg(unix)]
+mod set;
+mod stdio;
+#[cfg(unix)]
+mod supervisor;
+
+#[cfg(all(target_os = "linux", feature = "cgroup"))]
+pub use crate::cgroup::Cgroup;
+
+pub use crate::error::Error;
+#[cfg(feature = "expect")]
+pub use crate::expect::{Match, Pattern, Session};
+pub use crate::interleave::{InterleavedOutput, Stream};
+pub use crate::limit::{LimitedOutput, Overflow};
+pub use crate::pipeline::{Pipeline, PipelineChild, PipelineOutput, PipelineStatus};
+pub use crate::pool::ProcessPool;
+#[cfg(target_os = "linux")]
+pub use crate::pty::{PtyConfig, PtyMaster};
+pub use crate::stdio::{ChildStderr, ChildStdin, ChildStdout};
+
+#[cfg(target_os = "linux")]
+#[doc(hidden)]
+pub use crate::imp::force_sigchld_reaper;
+#[cfg(unix)]
+pub use crate::imp::{ProcessGroup, ResourceUsage, Signal, Termination};
+#[cfg(unix)]
+pub use crate::set::ChildSet;
+#[cfg(unix)]
+pub use crate::supervisor::{RestartPolicy, Supervisor, SupervisorEvent, SupervisorHandle};
 
 #[path = "unix.rs"]
 #[cfg(unix)]
@@ -75,6 +123,19 @@ mod imp;
 /// ```
 pub struct Command {
     inner: process::Command,
+    kill_on_drop: bool,
+    kill_on_cancel: bool,
+    max_output: Option<OutputLimit>,
+    // the stdin data of the next child, the mutex keeps the command `Sync`
+    input: Option<Mutex<Box<dyn Read + Send>>>,
+    #[cfg(unix)]
+    process_group: Option<ProcessGroup>,
+    #[cfg(unix)]
+    redirect: Option<imp::Redirect>,
+    #[cfg(target_os = "linux")]
+    pty: Option<pty::Pty>,
+    #[cfg(all(target_os = "linux", feature = "cgroup"))]
+    cgroup: Option<cgroup::Placement>,
 }
 
 impl Command {
@@ -111,6 +172,18 @@ impl Command {
     pub fn new<S: AsRef<OsStr>>(program: S) -> Command {
         Command {
             inner: process::Command::new(program),
+            kill_on_drop: false,
+            kill_on_cancel: false,
+            max_output: None,
+            input: None,
+            #[cfg(unix)]
+            process_group: None,
+            #[cfg(unix)]
+            redirect: None,
+            #[cfg(target_os = "linux")]
+            pty: None,
+            #[cfg(all(target_os = "linux", feature = "cgroup"))]
+            cgroup: None,
         }
     }
 
@@ -378,6 +451,313 @@ impl Command {
         self
     }
 
+    /// Controls whether the child is killed when its `Child` handle is
+    /// dropped.
+    ///
+    /// By default a child keeps running after its handle has gone out of
+    /// scope. With `kill_on_drop(true)` dropping the handle of a running
+    /// child kills it, which makes sure that a panicking or cancelled
+    /// coroutine doesn't leak processes. The killed child is reaped in the
+    /// background, so dropping the handle never blocks.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    ///
+    /// let child = Command::new("yes")
+    ///         .kill_on_drop(true)
+    ///         .spawn()
+    ///         .expect("yes command failed to start");
+    ///
+    /// // the yes command is killed here
+    /// drop(child);
+    /// ```
+    pub fn kill_on_drop(&mut self, kill_on_drop: bool) -> &mut Command {
+        self.kill_on_drop = kill_on_drop;
+        self
+    }
+
+    /// Feeds the stdin of the next spawned child from `input`.
+    ///
+    /// The stdin is captured through a pipe, and all the data of `input` is
+    /// written to it in a new coroutine, which closes the pipe when done.
+    /// This way the input is written while the output is read, e.g. by
+    /// [`output`], so a child that writes before reading all of its input
+    /// doesn't deadlock. The child doesn't have to read all the input.
+    ///
+    /// The input is used by one child only. Note that `input` itself is
+    /// read in the coroutine, so it should not block the worker thread.
+    ///
+    /// [`output`]: #method.output
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    /// use std::io::Cursor;
+    ///
+    /// let output = Command::new("gzip")
+    ///         .input_from(Cursor::new(vec![0; 1 << 20]))
+    ///         .output()
+    ///         .expect("gzip command failed to start");
+    /// ```
+    pub fn input_from<R: Read + Send + 'static>(&mut self, input: R) -> &mut Command {
+        self.input = Some(Mutex::new(Box::new(input)));
+        self
+    }
+
+    /// Controls whether the child is killed when the coroutine waiting for
+    /// it is cancelled.
+    ///
+    /// If a coroutine is cancelled while blocked in [`Child::wait`] (or any
+    /// other function that waits for the child), the child is left running
+    /// by default. On unix it's reaped in the background once it exits, so
+    /// it won't be left as a zombie. With `kill_on_cancel(true)` the child
+    /// is killed as well when the wait is cancelled.
+    ///
+    /// [`Child::wait`]: struct.Child.html#method.wait
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate may;
+    /// use may_process::Command;
+    ///
+    /// # fn main() {
+    /// let h = go!(|| {
+    ///     Command::new("yes")
+    ///             .kill_on_cancel(true)
+    ///             .status()
+    /// });
+    ///
+    /// // the yes command is killed by the cancel
+    /// unsafe { h.coroutine().cancel() };
+    /// assert!(h.join().is_err());
+    /// # }
+    /// ```
+    pub fn kill_on_cancel(&mut self, kill_on_cancel: bool) -> &mut Command {
+        self.kill_on_cancel = kill_on_cancel;
+        self
+    }
+
+    /// Limits how much of stdout and stderr is collected by [`output`] and
+    /// the like, `overflow` decides what happens to a child that writes
+    /// more than `bytes` bytes to either of them.
+    ///
+    /// The limit applies to each stream separately. With
+    /// [`Overflow::Error`] the child is killed and an error is returned,
+    /// while with the other policies the collected data is truncated,
+    /// which is reported by [`limited_output`]. A child killed with
+    /// [`Overflow::Kill`] exits with the status of the kill.
+    ///
+    /// [`output`]: #method.output
+    /// [`limited_output`]: #method.limited_output
+    /// [`Overflow::Error`]: enum.Overflow.html#variant.Error
+    /// [`Overflow::Kill`]: enum.Overflow.html#variant.Kill
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use may_process::{Command, Overflow};
+    ///
+    /// let output = Command::new("yes")
+    ///         .max_output(1 << 20, Overflow::Kill)
+    ///         .limited_output()
+    ///         .expect("yes command failed to start");
+    ///
+    /// assert!(output.truncated);
+    /// assert_eq!(output.stdout.len(), 1 << 20);
+    /// ```
+    pub fn max_output(&mut self, bytes: usize, overflow: Overflow) -> &mut Command {
+        self.max_output = Some(OutputLimit {
+            max: bytes,
+            overflow,
+        });
+        self
+    }
+
+    /// Sets the process group of the child process.
+    ///
+    /// The child calls `setpgid` right before executing the program, either
+    /// creating a new process group led by itself or joining an existing one.
+    /// All the processes the child spawns stay in that group by default, so
+    /// the whole job can be torn down with [`Child::kill_group`].
+    ///
+    /// [`Child::kill_group`]: struct.Child.html#method.kill_group
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use may_process::{Command, ProcessGroup};
+    ///
+    /// let mut child = Command::new("sh")
+    ///         .args(&["-c", "sleep 10 & sleep 10"])
+    ///         .process_group(ProcessGroup::New)
+    ///         .spawn()
+    ///         .expect("sh command failed to start");
+    ///
+    /// // both of the sleep commands are killed
+    /// child.kill_group().expect("command wasn't running");
+    /// ```
+    #[cfg(unix)]
+    pub fn process_group(&mut self, group: ProcessGroup) -> &mut Command {
+        use std::os::unix::process::CommandExt;
+
+        let pgid = match group {
+            ProcessGroup::New => 0,
+            ProcessGroup::Join(pgid) => pgid as i32,
+        };
+        self.inner.process_group(pgid);
+        self.process_group = Some(group);
+        self
+    }
+
+    /// Redirects the stderr of the child process into its stdout, like
+    /// `2>&1` in the shell.
+    ///
+    /// The child points its stderr to the same file as its stdout right
+    /// before executing the program, whatever the stdout is configured to,
+    /// so the two streams are merged in the order they are written. With
+    /// [`output`] the merged stream is returned in `stdout`, and `stderr`
+    /// is empty.
+    ///
+    /// This overrides the configuration of [`stderr`], and a later call to
+    /// [`stdout_to_stderr`] replaces it.
+    ///
+    /// [`output`]: #method.output
+    /// [`stderr`]: #method.stderr
+    /// [`stdout_to_stderr`]: #method.stdout_to_stderr
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    ///
+    /// let output = Command::new("sh")
+    ///         .args(&["-c", "echo out; echo err >&2"])
+    ///         .stderr_to_stdout()
+    ///         .output()
+    ///         .expect("sh command failed to start");
+    ///
+    /// assert_eq!(output.stdout, b"out\nerr\n");
+    /// ```
+    #[cfg(unix)]
+    pub fn stderr_to_stdout(&mut self) -> &mut Command {
+        imp::Redirect::set(&mut self.redirect, &mut self.inner, 1);
+        self
+    }
+
+    /// Redirects the stdout of the child process into its stderr, like
+    /// `1>&2` in the shell.
+    ///
+    /// This is the reverse of [`stderr_to_stdout`], the merged stream is
+    /// returned in `stderr` by [`output`].
+    ///
+    /// [`stderr_to_stdout`]: #method.stderr_to_stdout
+    /// [`output`]: #method.output
+    #[cfg(unix)]
+    pub fn stdout_to_stderr(&mut self) -> &mut Command {
+        imp::Redirect::set(&mut self.redirect, &mut self.inner, 2);
+        self
+    }
+
+    /// Runs the child process on a new pseudo-terminal.
+    ///
+    /// The stdin, stdout and stderr of the child are connected to the slave
+    /// side of a new pty, and the child becomes the leader of a new session
+    /// with the pty as its controlling terminal. The parent talks to the
+    /// child through the master side returned by [`Child::pty`]. Each
+    /// spawned child gets a pty of its own.
+    ///
+    /// This overrides the stdio configuration of the command, except for
+    /// the stdin fed by [`input_from`]. It can't be combined with
+    /// [`process_group`], since a process group leader can't start a new
+    /// session, spawning fails with an error of kind `InvalidInput` then.
+    ///
+    /// This method is only available on Linux.
+    ///
+    /// [`Child::pty`]: struct.Child.html#method.pty
+    /// [`input_from`]: #method.input_from
+    /// [`process_group`]: #method.process_group
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use may_process::{Command, PtyConfig};
+    /// use std::io::Read;
+    ///
+    /// let mut child = Command::new("ls")
+    ///         .arg("--color=auto")
+    ///         .pty(PtyConfig::new())
+    ///         .spawn()
+    ///         .expect("ls command failed to start");
+    ///
+    /// // ls writes colored output to a terminal
+    /// let mut output = String::new();
+    /// child.pty().unwrap().read_to_string(&mut output).unwrap();
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn pty(&mut self, config: PtyConfig) -> &mut Command {
+        pty::Pty::set(&mut self.pty, &mut self.inner, config);
+        self
+    }
+
+    /// Places the child process into the cgroup `group`.
+    ///
+    /// The child is moved into the cgroup before it executes the program,
+    /// so all of its descendants are contained in the cgroup as well.
+    /// Several commands can share one cgroup to manage a group of children.
+    ///
+    /// This method is only available with the `cgroup` feature on Linux.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may_process::{Cgroup, Command};
+    ///
+    /// let group = Cgroup::new("jobs").expect("can't create cgroup");
+    /// Command::new("ls")
+    ///         .cgroup(&group)
+    ///         .spawn()
+    ///         .expect("ls command failed to start");
+    /// ```
+    #[cfg(all(target_os = "linux", feature = "cgroup"))]
+    pub fn cgroup(&mut self, group: &Cgroup) -> &mut Command {
+        cgroup::Placement::set(&mut self.cgroup, &mut self.inner, Some(group));
+        self
+    }
+
+    /// Places each spawned child process into a fresh cgroup, created under
+    /// the cgroup of the current process. No controllers are enabled for
+    /// it, so it contains the processes without limiting them.
+    ///
+    /// The cgroup is available through [`Child::cgroup`]. It's owned by the
+    /// `Child`, so dropping the `Child` kills all the processes in the cgroup.
+    ///
+    /// This method is only available with the `cgroup` feature on Linux.
+    ///
+    /// [`Child::cgroup`]: struct.Child.html#method.cgroup
+    #[cfg(all(target_os = "linux", feature = "cgroup"))]
+    pub fn fresh_cgroup(&mut self) -> &mut Command {
+        cgroup::Placement::set(&mut self.cgroup, &mut self.inner, None);
+        self
+    }
+
     /// Executes the command as a child process, returning a handle to it.
     ///
     /// By default, stdin, stdout and stderr are inherited from the parent.
@@ -394,9 +774,7 @@ impl Command {
     ///         .expect("ls command failed to start");
     /// ```
     pub fn spawn(&mut self) -> io::Result<Child> {
-        self.inner.spawn().map(|p| Child {
-            inner: imp::Child::new(p),
-        })
+        self.spawn_inner()
     }
 
     /// Executes the command as a child process, waiting for it to finish and
@@ -423,15 +801,127 @@ impl Command {
     /// assert!(output.status.success());
     /// ```
     pub fn output(&mut self) -> io::Result<Output> {
-        self.inner
-            .stdin(process::Stdio::null())
-            .stdout(process::Stdio::piped())
-            .stderr(process::Stdio::piped())
-            .spawn()
-            .map(|p| Child {
-                inner: imp::Child::new(p),
-            })
-            .and_then(|p| p.wait_with_output())
+        self.spawn_piped().and_then(|p| p.wait_with_output())
+    }
+
+    /// Executes the command as a child process with `input` written to its
+    /// stdin, waiting for it to finish and collecting all of its output.
+    ///
+    /// This is the same as [`output`] after [`input_from`], the input is
+    /// written while the output is read.
+    ///
+    /// [`output`]: #method.output
+    /// [`input_from`]: #method.input_from
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    ///
+    /// let output = Command::new("jq")
+    ///                      .arg(".name")
+    ///                      .output_with_input(br#"{"name": "may"}"#)
+    ///                      .expect("failed to execute process");
+    ///
+    /// assert_eq!(output.stdout, b"\"may\"\n");
+    /// ```
+    pub fn output_with_input(&mut self, input: &[u8]) -> io::Result<Output> {
+        self.input_from(io::Cursor::new(input.to_vec())).output()
+    }
+
+    /// Executes the command as a child process, waiting at most `timeout`
+    /// for it to finish and collecting all of its output.
+    ///
+    /// The output is captured the same way as [`output`] does. If the child
+    /// is still running when the timeout elapses it is killed and reaped,
+    /// and an error of kind `TimedOut` is returned.
+    ///
+    /// [`output`]: #method.output
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    /// use std::io;
+    /// use std::time::Duration;
+    ///
+    /// let ret = Command::new("sleep")
+    ///                   .arg("10")
+    ///                   .output_timeout(Duration::from_secs(1));
+    ///
+    /// assert_eq!(ret.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    /// ```
+    pub fn output_timeout(&mut self, timeout: Duration) -> io::Result<Output> {
+        let deadline = Instant::now() + timeout;
+        self.spawn_piped()
+            .and_then(|mut p| p.collect_output(Some(deadline)))
+            .map(Output::from)
+    }
+
+    /// Executes the command as a child process, waiting for it to finish and
+    /// collecting its output within the limit set by [`max_output`].
+    ///
+    /// This is the same as [`output`] except that the returned output tells
+    /// whether the data is truncated.
+    ///
+    /// [`max_output`]: #method.max_output
+    /// [`output`]: #method.output
+    pub fn limited_output(&mut self) -> io::Result<LimitedOutput> {
+        self.spawn_piped()
+            .and_then(|p| p.wait_with_limited_output())
+    }
+
+    /// Executes the command as a child process, waiting for it to finish and
+    /// collecting its stdout and stderr interleaved in the order they were
+    /// written.
+    ///
+    /// The pipes are set up the same as [`output`] does. Each chunk of the
+    /// output is stamped with the time it was read, which tells how the two
+    /// streams interleaved.
+    ///
+    /// The limit set by [`max_output`] applies to the total of both streams
+    /// here, since they are collected as one log.
+    ///
+    /// [`output`]: #method.output
+    /// [`max_output`]: #method.max_output
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    ///
+    /// let output = Command::new("make")
+    ///                      .output_interleaved()
+    ///                      .expect("failed to execute process");
+    ///
+    /// print!("{}", output.render());
+    /// ```
+    pub fn output_interleaved(&mut self) -> io::Result<InterleavedOutput> {
+        self.spawn_piped()
+            .and_then(|p| p.wait_with_output_interleaved())
+    }
+
+    /// Executes the command as a child process, waiting for it to finish and
+    /// collecting all of its output, the same as [`output`], together with
+    /// the resource usage of the child.
+    ///
+    /// [`output`]: #method.output
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    ///
+    /// let (output, usage) = Command::new("ls")
+    ///                               .output_with_rusage()
+    ///                               .expect("failed to execute process");
+    ///
+    /// println!("status: {}", output.status);
+    /// println!("user time: {:?}", usage.user_time);
+    /// ```
+    #[cfg(unix)]
+    pub fn output_with_rusage(&mut self) -> io::Result<(Output, ResourceUsage)> {
+        self.spawn_piped().and_then(|p| p.wait_with_output_rusage())
     }
 
     /// Executes a command as a child process, waiting for it to finish and
@@ -456,6 +946,159 @@ impl Command {
     pub fn status(&mut self) -> io::Result<ExitStatus> {
         self.spawn().and_then(|mut p| p.wait())
     }
+
+    /// Executes a command as a child process, waiting at most `timeout` for
+    /// it to finish and collecting its exit status.
+    ///
+    /// If the child is still running when the timeout elapses it is killed
+    /// and reaped, and an error of kind `TimedOut` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    /// use std::time::Duration;
+    ///
+    /// let status = Command::new("ls")
+    ///                      .status_timeout(Duration::from_secs(5))
+    ///                      .expect("failed to execute process");
+    ///
+    /// assert!(status.success());
+    /// ```
+    pub fn status_timeout(&mut self, timeout: Duration) -> io::Result<ExitStatus> {
+        let deadline = Instant::now() + timeout;
+        let mut child = self.spawn()?;
+        match child.wait_deadline(deadline)? {
+            Some(status) => Ok(status),
+            None => {
+                child.kill()?;
+                child.wait()?;
+                Err(timed_out())
+            }
+        }
+    }
+
+    /// Executes the command as a child process, collecting all of its output
+    /// the same as [`output`], and checks that it exited successfully.
+    ///
+    /// If the command can't be run or exits with a failure, the returned
+    /// [`Error`] records the command line, the exit status and the tail of
+    /// the captured stderr.
+    ///
+    /// [`output`]: #method.output
+    /// [`Error`]: struct.Error.html
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    ///
+    /// match Command::new("ls").arg("missing").checked_output() {
+    ///     Ok(output) => println!("{}", String::from_utf8_lossy(&output.stdout)),
+    ///     Err(e) => println!("{}", e),
+    /// }
+    /// ```
+    pub fn checked_output(&mut self) -> Result<Output, Error> {
+        match self.output() {
+            Ok(output) if output.status.success() => Ok(output),
+            Ok(output) => Err(Error::failed(self, output.status, &output.stderr)),
+            Err(e) => Err(Error::io(self, e)),
+        }
+    }
+
+    /// Executes the command as a child process, waiting for it to finish
+    /// the same as [`status`], and checks that it exited successfully.
+    ///
+    /// The stderr is not captured, so the returned [`Error`] only records
+    /// the command line and the exit status.
+    ///
+    /// [`status`]: #method.status
+    /// [`Error`]: struct.Error.html
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    ///
+    /// fn build() -> std::io::Result<()> {
+    ///     Command::new("make").checked_status()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn checked_status(&mut self) -> Result<ExitStatus, Error> {
+        match self.status() {
+            Ok(status) if status.success() => Ok(status),
+            Ok(status) => Err(Error::failed(self, status, &[])),
+            Err(e) => Err(Error::io(self, e)),
+        }
+    }
+
+    // spawn the child with the stdio setup of `output`
+    fn spawn_piped(&mut self) -> io::Result<Child> {
+        self.inner
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped());
+        self.spawn_inner()
+    }
+
+    // spawn the child and wrap it with the options of the command
+    fn spawn_inner(&mut self) -> io::Result<Child> {
+        #[cfg(target_os = "linux")]
+        if self.pty.is_some() && self.process_group.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a pty can't be combined with a process group, \
+                 the child leads a new session and process group on the pty",
+            ));
+        }
+        #[cfg(all(target_os = "linux", feature = "cgroup"))]
+        let cgroup = match self.cgroup {
+            Some(ref placement) => Some(placement.prepare()?),
+            None => None,
+        };
+
+        #[cfg(target_os = "linux")]
+        let pty = match self.pty {
+            Some(ref pty) => Some(pty.open(&mut self.inner)?),
+            None => None,
+        };
+        if self.input.is_some() {
+            self.inner.stdin(Stdio::piped());
+        }
+
+        let ret = self.inner.spawn();
+        #[cfg(target_os = "linux")]
+        if pty.is_some() {
+            pty::Pty::close(&mut self.inner);
+        }
+        let mut child = Child::new(ret?)?;
+        if let Some(input) = self.input.take() {
+            let input = input.into_inner().unwrap_or_else(|e| e.into_inner());
+            let stdin = child.stdin.take().expect("stdin is piped");
+            child.input = Some(feed(stdin, input));
+        }
+        child.kill_on_drop = self.kill_on_drop;
+        child.kill_on_cancel = self.kill_on_cancel;
+        child.max_output = self.max_output;
+        #[cfg(unix)]
+        {
+            child.pgid = match self.process_group {
+                Some(ProcessGroup::New) => Some(child.id()),
+                Some(ProcessGroup::Join(pgid)) => Some(pgid),
+                None => None,
+            };
+        }
+        #[cfg(target_os = "linux")]
+        {
+            child.pty = pty.map(pty::Opened::into_master);
+        }
+        #[cfg(all(target_os = "linux", feature = "cgroup"))]
+        {
+            child.cgroup = cgroup.map(cgroup::Prepared::into_cgroup);
+        }
+        Ok(child)
+    }
 }
 
 impl fmt::Debug for Command {
@@ -474,10 +1117,12 @@ impl fmt::Debug for Command {
 /// spawning process and can itself be constructed using a builder-style
 /// interface.
 ///
-/// There is no implementation of [`Drop`] for child processes,
-/// so if you do not ensure the `Child` has exited then it will continue to
-/// run, even after the `Child` handle to the child process has gone out of
-/// scope.
+/// By default dropping a `Child` doesn't kill the child process, so if you
+/// do not ensure the `Child` has exited then it will continue to run, even
+/// after the `Child` handle to the child process has gone out of scope.
+/// Use [`kill_on_drop`] to kill the child when the handle is dropped. On
+/// unix the exited child is always reaped in the background, so it won't
+/// be left as a zombie.
 ///
 /// Calling [`wait`](#method.wait) (or other functions that wrap around it) will make
 /// the parent process wait until the child has actually exited before
@@ -500,10 +1145,34 @@ impl fmt::Debug for Command {
 /// ```
 ///
 /// [`Command`]: struct.Command.html
+/// [`kill_on_drop`]: struct.Command.html#method.kill_on_drop
 /// [`wait`]: #method.wait
 pub struct Child {
     /// actual inner child
     inner: imp::Child,
+    stdin: Option<ChildStdin>,
+    stdout: Option<ChildStdout>,
+    stderr: Option<ChildStderr>,
+    kill_on_drop: bool,
+    kill_on_cancel: bool,
+    max_output: Option<OutputLimit>,
+    // the coroutine writing the input of `Command::input_from`
+    input: Option<JoinHandle<io::Result<()>>>,
+    #[cfg(unix)]
+    pgid: Option<u32>,
+    #[cfg(target_os = "linux")]
+    pty: Option<PtyMaster>,
+    #[cfg(all(target_os = "linux", feature = "cgroup"))]
+    cgroup: Option<Cgroup>,
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        if self.kill_on_drop {
+            // the child would be reaped in the background
+            let _ = self.kill();
+        }
+    }
 }
 
 impl fmt::Debug for Child {
@@ -513,9 +1182,129 @@ impl fmt::Debug for Child {
 }
 
 impl Child {
+    fn new(mut child: process::Child) -> io::Result<Child> {
+        let stdin = child.stdin.take().map(ChildStdin::new);
+        let stdout = child.stdout.take().map(ChildStdout::new);
+        let stderr = child.stderr.take().map(ChildStderr::new);
+        Ok(Child {
+            inner: imp::Child::new(child)?,
+            stdin,
+            stdout,
+            stderr,
+            kill_on_drop: false,
+            kill_on_cancel: false,
+            max_output: None,
+            input: None,
+            #[cfg(unix)]
+            pgid: None,
+            #[cfg(target_os = "linux")]
+            pty: None,
+            #[cfg(all(target_os = "linux", feature = "cgroup"))]
+            cgroup: None,
+        })
+    }
+
+    /// Returns a handle for writing to the child's standard input (stdin),
+    /// if it has been captured with `stdin(Stdio::piped())`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    /// use std::io::Write;
+    /// use std::process::Stdio;
+    ///
+    /// let mut child = Command::new("cat")
+    ///     .stdin(Stdio::piped())
+    ///     .spawn()
+    ///     .expect("failed to execute child");
+    ///
+    /// child.stdin().unwrap().write_all(b"hello").unwrap();
+    /// ```
+    pub fn stdin(&mut self) -> Option<&mut ChildStdin> {
+        self.stdin.as_mut()
+    }
+
+    /// Returns a handle for reading from the child's standard output (stdout),
+    /// if it has been captured with `stdout(Stdio::piped())`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    /// use std::io::Read;
+    /// use std::process::Stdio;
+    ///
+    /// let mut child = Command::new("echo")
+    ///     .arg("hello")
+    ///     .stdout(Stdio::piped())
+    ///     .spawn()
+    ///     .expect("failed to execute child");
+    ///
+    /// let mut hello = String::new();
+    /// child.stdout().unwrap().read_to_string(&mut hello).unwrap();
+    /// ```
+    pub fn stdout(&mut self) -> Option<&mut ChildStdout> {
+        self.stdout.as_mut()
+    }
+
+    /// Returns a handle for reading from the child's standard error (stderr),
+    /// if it has been captured with `stderr(Stdio::piped())`.
+    pub fn stderr(&mut self) -> Option<&mut ChildStderr> {
+        self.stderr.as_mut()
+    }
+
+    /// Takes ownership of the child's stdin handle, leaving `None` in its
+    /// place.
+    ///
+    /// Dropping the returned handle closes the pipe, which signals the end
+    /// of input to the child.
+    pub fn take_stdin(&mut self) -> Option<ChildStdin> {
+        self.stdin.take()
+    }
+
+    /// Takes ownership of the child's stdout handle, leaving `None` in its
+    /// place.
+    ///
+    /// This is useful for reading the output in another coroutine.
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.stdout.take()
+    }
+
+    /// Takes ownership of the child's stderr handle, leaving `None` in its
+    /// place.
+    ///
+    /// This is useful for reading the output in another coroutine.
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.stderr.take()
+    }
+
+    /// Returns the master side of the child's pseudo-terminal, if it has
+    /// been spawned with [`Command::pty`].
+    ///
+    /// This method is only available on Linux.
+    ///
+    /// [`Command::pty`]: struct.Command.html#method.pty
+    #[cfg(target_os = "linux")]
+    pub fn pty(&mut self) -> Option<&mut PtyMaster> {
+        self.pty.as_mut()
+    }
+
+    /// Takes the master side of the child's pseudo-terminal out of the
+    /// `Child`, see [`pty`].
+    ///
+    /// [`pty`]: #method.pty
+    #[cfg(target_os = "linux")]
+    pub fn take_pty(&mut self) -> Option<PtyMaster> {
+        self.pty.take()
+    }
+
     /// Forces the child to exit. This is equivalent to sending a
     /// SIGKILL on unix platforms.
     ///
+    /// Once the exit status of the child has been collected this is a no-op
+    /// that returns `Ok(())`, so a recycled process id is never killed.
+    ///
     /// # Examples
     ///
     /// Basic usage:
@@ -534,6 +1323,119 @@ impl Child {
         self.inner.kill()
     }
 
+    /// Sends the signal `sig` to the child.
+    ///
+    /// Once the exit status of the child has been collected this is a no-op
+    /// that returns `Ok(())`, so a recycled process id is never signaled.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use may_process::{Command, Signal};
+    ///
+    /// let mut child = Command::new("yes").spawn().unwrap();
+    /// child.signal(Signal::Term).expect("command wasn't running");
+    /// ```
+    #[cfg(unix)]
+    pub fn signal(&mut self, sig: Signal) -> io::Result<()> {
+        self.inner.signal(sig)
+    }
+
+    /// Returns the id of the process group of the child, if it's spawned
+    /// with [`Command::process_group`].
+    ///
+    /// [`Command::process_group`]: struct.Command.html#method.process_group
+    #[cfg(unix)]
+    pub fn process_group_id(&self) -> Option<u32> {
+        self.pgid
+    }
+
+    /// Returns the cgroup that the child is placed into, if it's spawned
+    /// with [`Command::cgroup`] or [`Command::fresh_cgroup`].
+    ///
+    /// The cgroup can be used to set resource limits for the child and all
+    /// of its descendants, or to kill them as a whole.
+    ///
+    /// This method is only available with the `cgroup` feature on Linux.
+    ///
+    /// [`Command::cgroup`]: struct.Command.html#method.cgroup
+    /// [`Command::fresh_cgroup`]: struct.Command.html#method.fresh_cgroup
+    #[cfg(all(target_os = "linux", feature = "cgroup"))]
+    pub fn cgroup(&self) -> Option<&Cgroup> {
+        self.cgroup.as_ref()
+    }
+
+    /// Sends the signal `sig` to all the processes in the process group of
+    /// the child.
+    ///
+    /// An error of kind `InvalidInput` is returned if the child is not spawned
+    /// with [`Command::process_group`], since it's in our own process group
+    /// then. Signaling a group whose processes have all exited is a no-op.
+    ///
+    /// The id of the group stays reserved only as long as some process is
+    /// in the group or the child is not reaped yet. The reaper reaps the
+    /// child in the background as soon as it exits, so once all the
+    /// processes of the group have exited, the id can be reused by an
+    /// unrelated process group, which would receive the signal instead.
+    ///
+    /// [`Command::process_group`]: struct.Command.html#method.process_group
+    #[cfg(unix)]
+    pub fn signal_group(&mut self, sig: Signal) -> io::Result<()> {
+        match self.pgid {
+            Some(pgid) => imp::signal_group(pgid, sig),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the child is not spawned with a process group",
+            )),
+        }
+    }
+
+    /// Forces all the processes in the process group of the child to exit,
+    /// which is the same as `signal_group(Signal::Kill)`.
+    ///
+    /// This tears down the whole job, including the grandchildren that
+    /// [`kill`] would leave running.
+    ///
+    /// [`kill`]: #method.kill
+    #[cfg(unix)]
+    pub fn kill_group(&mut self) -> io::Result<()> {
+        self.signal_group(Signal::Kill)
+    }
+
+    /// Terminates the child gracefully, returning how it ended.
+    ///
+    /// A SIGTERM is sent to the child first. If it's still running after
+    /// `grace_period`, a SIGKILL is sent and the child is reaped, which is
+    /// reported as [`Termination::Killed`].
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    /// use std::time::Duration;
+    ///
+    /// let mut child = Command::new("yes").spawn().unwrap();
+    /// let termination = child.terminate(Duration::from_secs(5)).unwrap();
+    /// if termination.killed() {
+    ///     println!("yes command didn't exit gracefully");
+    /// }
+    /// ```
+    ///
+    /// [`Termination::Killed`]: enum.Termination.html#variant.Killed
+    #[cfg(unix)]
+    pub fn terminate(&mut self, grace_period: Duration) -> io::Result<Termination> {
+        self.signal(Signal::Term)?;
+        if let Some(status) = self.wait_timeout(grace_period)? {
+            return Ok(Termination::Graceful(status));
+        }
+        self.kill()?;
+        self.wait().map(Termination::Killed)
+    }
+
     /// Returns the OS-assigned process identifier associated with this child.
     ///
     /// # Examples
@@ -579,7 +1481,34 @@ impl Child {
     /// }
     /// ```
     pub fn wait(&mut self) -> io::Result<ExitStatus> {
-        self.inner.wait()
+        drop(self.stdin.take());
+        self.cancel_guard().child.wait()
+    }
+
+    /// Waits for the child to exit completely, returning the status that it
+    /// exited with together with the resource usage of the child.
+    ///
+    /// The usage is reported by `wait4` when the child is reaped, and
+    /// covers the child and all of its descendants that it has waited for.
+    /// This function will continue to have the same return value after it
+    /// has been called at least once.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    ///
+    /// let mut child = Command::new("ls").spawn().unwrap();
+    /// let (status, usage) = child.wait_with_rusage().unwrap();
+    /// println!("{} after {:?} of cpu time, max rss {} bytes",
+    ///          status, usage.user_time + usage.system_time, usage.max_rss);
+    /// ```
+    #[cfg(unix)]
+    pub fn wait_with_rusage(&mut self) -> io::Result<(ExitStatus, ResourceUsage)> {
+        drop(self.stdin.take());
+        self.cancel_guard().child.wait_with_rusage()
     }
 
     /// Attempts to collect the exit status of the child if it has already
@@ -620,6 +1549,53 @@ impl Child {
         self.inner.try_wait()
     }
 
+    /// Waits for the child to exit, but no longer than `timeout`.
+    ///
+    /// If the child has exited, then `Ok(Some(status))` is returned. If the
+    /// timeout elapses before that, `Ok(None)` is returned and the child is
+    /// left running.
+    ///
+    /// Note that unlike `wait`, this function will not attempt to drop stdin,
+    /// so the child can still be fed after a timeout.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use may_process::Command;
+    /// use std::time::Duration;
+    ///
+    /// let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+    ///
+    /// match child.wait_timeout(Duration::from_secs(1)) {
+    ///     Ok(Some(status)) => println!("exited with: {}", status),
+    ///     Ok(None) => child.kill().expect("command wasn't running"),
+    ///     Err(e) => println!("error attempting to wait: {}", e),
+    /// }
+    /// ```
+    pub fn wait_timeout(&mut self, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+        self.wait_deadline(Instant::now() + timeout)
+    }
+
+    /// Waits for the child to exit, but no longer than until `deadline`.
+    ///
+    /// This is the same as [`wait_timeout`] except that the limit is given
+    /// as a point in time, which is handy when several waits share a budget.
+    ///
+    /// [`wait_timeout`]: #method.wait_timeout
+    pub fn wait_deadline(&mut self, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+        self.cancel_guard().child.wait_deadline(deadline)
+    }
+
+    // guard a blocking wait against the cancel of the coroutine
+    fn cancel_guard(&mut self) -> CancelGuard<'_> {
+        CancelGuard {
+            child: &mut self.inner,
+            kill: self.kill_on_cancel,
+        }
+    }
+
     /// Simultaneously waits for the child to exit and collect all remaining
     /// output on the stdout/stderr handles, returning an `Output`
     /// instance.
@@ -654,42 +1630,172 @@ impl Child {
     /// ```
     ///
     pub fn wait_with_output(mut self) -> io::Result<Output> {
-        use may::io::CoIo;
-
-        // first create the io object
-        // if the remote pipe is closed, MacOs would return libc::EPIPE
-        // when register the fd to the event loop
-        macro_rules! async_read_data {
-            ($io: expr) => {{
-                let mut output = Vec::new();
-                match CoIo::new($io) {
-                    Ok(mut o) => o.read_to_end(&mut output)?,
-                    Err(e) => {
-                        // if failed to create CoIo then use default
-                        let mut out = e.into_data();
-                        out.read_to_end(&mut output)?
-                    }
-                };
-                output
-            }};
-        }
+        self.collect_output(None).map(Output::from)
+    }
 
-        let status = self.wait()?;
+    /// Simultaneously waits for the child to exit and collect the remaining
+    /// output on the stdout/stderr handles interleaved in the order they
+    /// were written, see [`Command::output_interleaved`].
+    ///
+    /// [`Command::output_interleaved`]: struct.Command.html#method.output_interleaved
+    pub fn wait_with_output_interleaved(mut self) -> io::Result<InterleavedOutput> {
+        drop(self.stdin.take());
+        let stdout = self.stdout.take();
+        let stderr = self.stderr.take();
+        let input = self.input.take();
 
-        let stdout = match self.inner.child.stdout.take() {
-            Some(o) => async_read_data!(o),
-            None => Vec::new(),
+        let limit = self.max_output;
+        let guard = self.cancel_guard();
+        let kill = || {
+            guard.child.kill()?;
+            guard.child.wait().map(drop)
         };
+        let (chunks, truncated) = interleave::collect(stdout, stderr, limit, kill)?;
+        let status = guard.child.wait()?;
+        join_input(input)?;
+        Ok(InterleavedOutput {
+            status,
+            chunks,
+            truncated,
+        })
+    }
+
+    /// Simultaneously waits for the child to exit and collect the remaining
+    /// output within the limit set by [`Command::max_output`].
+    ///
+    /// This is the same as [`wait_with_output`] except that the returned
+    /// output tells whether the data is truncated.
+    ///
+    /// [`Command::max_output`]: struct.Command.html#method.max_output
+    /// [`wait_with_output`]: #method.wait_with_output
+    pub fn wait_with_limited_output(mut self) -> io::Result<LimitedOutput> {
+        self.collect_output(None)
+    }
+
+    /// Simultaneously waits for the child to exit and collect all remaining
+    /// output on the stdout/stderr handles, the same as [`wait_with_output`],
+    /// also returning the resource usage of the child.
+    ///
+    /// [`wait_with_output`]: #method.wait_with_output
+    #[cfg(unix)]
+    pub fn wait_with_output_rusage(mut self) -> io::Result<(Output, ResourceUsage)> {
+        let output = self.collect_output(None)?.into();
+        let (_, usage) = self.inner.wait_with_rusage()?;
+        Ok((output, usage))
+    }
+
+    // collect the output, the child is killed if it's not done before deadline
+    fn collect_output(&mut self, deadline: Option<Instant>) -> io::Result<LimitedOutput> {
+        drop(self.stdin.take());
+
+        // the child may fill up either of the pipes before exiting, so both
+        // of them are drained concurrently, each reporting once it's done
+        let (tx, rx) = channel();
+        let limit = self.max_output;
+        let mut pending = 0;
+        if let Some(io) = self.stdout.take() {
+            limit::capture(io, limit, 0, tx.clone());
+            pending += 1;
+        }
+        if let Some(io) = self.stderr.take() {
+            limit::capture(io, limit, 1, tx);
+            pending += 1;
+        }
+        let input = self.input.take();
+
+        let guard = self.cancel_guard();
+        let mut data = [Vec::new(), Vec::new()];
+        let mut truncated = false;
+        for _ in 0..pending {
+            let ret = match deadline {
+                None => rx.recv().map_err(|_| ()),
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    match rx.recv_timeout(timeout) {
+                        Ok(ret) => Ok(ret),
+                        Err(RecvTimeoutError::Timeout) => {
+                            guard.child.kill()?;
+                            guard.child.wait()?;
+                            return Err(timed_out());
+                        }
+                        Err(RecvTimeoutError::Disconnected) => Err(()),
+                    }
+                }
+            };
+            let (i, captured) = ret.map_err(|_| io::Error::other("pipe reader panicked"))?;
+            let captured = captured?;
+            if captured.truncated {
+                truncated = true;
+                match limit {
+                    Some(limit) if limit.overflow == Overflow::Error => {
+                        guard.child.kill()?;
+                        guard.child.wait()?;
+                        return Err(io::Error::other("process output exceeds the limit"));
+                    }
+                    Some(limit) if limit.kills() => guard.child.kill()?,
+                    _ => {}
+                }
+            }
+            data[i] = captured.data;
+        }
 
-        let stderr = match self.inner.child.stderr.take() {
-            Some(o) => async_read_data!(o),
-            None => Vec::new(),
+        let status = match deadline {
+            None => guard.child.wait()?,
+            Some(deadline) => match guard.child.wait_deadline(deadline)? {
+                Some(status) => status,
+                None => {
+                    guard.child.kill()?;
+                    guard.child.wait()?;
+                    return Err(timed_out());
+                }
+            },
         };
+        let [stdout, stderr] = data;
+        join_input(input)?;
 
-        Ok(Output {
+        Ok(LimitedOutput {
             status,
             stdout,
             stderr,
+            truncated,
         })
     }
 }
+
+// a cancelled coroutine unwinds out of the wait, and the child
+// is killed then if it's configured with `kill_on_cancel`
+struct CancelGuard<'a> {
+    child: &'a mut imp::Child,
+    kill: bool,
+}
+
+impl<'a> Drop for CancelGuard<'a> {
+    fn drop(&mut self) {
+        if self.kill && std::thread::panicking() {
+            let _ = self.child.kill();
+        }
+    }
+}
+
+/// write all the data of `input` to the stdin of a child in a new coroutine
+fn feed(mut stdin: ChildStdin, mut input: Box<dyn Read + Send>) -> JoinHandle<io::Result<()>> {
+    may::go!(move || match io::copy(&mut input, &mut stdin) {
+        // the child doesn't have to read all of the input
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        ret => ret.map(drop),
+    })
+}
+
+/// collect the result of `feed`
+fn join_input(handle: Option<JoinHandle<io::Result<()>>>) -> io::Result<()> {
+    match handle {
+        Some(h) => h
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("stdin writer panicked"))),
+        None => Ok(()),
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "process timed out")
+}
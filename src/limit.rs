@@ -0,0 +1,141 @@
+//! Bounded capture of the output of a child process
+//!
+//! Each captured pipe is read in its own coroutine, which keeps at most the
+//! configured number of bytes. The result of each reader is sent to a
+//! channel as soon as it's done, so the waiting side can react to an
+//! overflow of either pipe right away.
+//!
+
+use std::io::{self, Read};
+use std::process::{ExitStatus, Output};
+
+use may::sync::mpsc::Sender;
+
+/// What to do when a child writes more than [`max_output`] bytes to one of
+/// its captured output streams.
+///
+/// [`max_output`]: struct.Command.html#method.max_output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Overflow {
+    /// Kill the child and return an error.
+    Error,
+    /// Keep the first bytes and discard the rest, the child keeps running.
+    KeepHead,
+    /// Keep the last bytes and discard the rest, the child keeps running.
+    KeepTail,
+    /// Keep the first bytes and kill the child.
+    Kill,
+}
+
+// the output limit of a command
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct OutputLimit {
+    pub(crate) max: usize,
+    pub(crate) overflow: Overflow,
+}
+
+impl OutputLimit {
+    // whether the child must be killed once the limit is exceeded
+    pub(crate) fn kills(&self) -> bool {
+        match self.overflow {
+            Overflow::Error | Overflow::Kill => true,
+            Overflow::KeepHead | Overflow::KeepTail => false,
+        }
+    }
+}
+
+/// The output of a finished process collected with an output limit.
+///
+/// This is the same as `std::process::Output` with an additional flag
+/// telling whether any of the streams is truncated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LimitedOutput {
+    /// The status (exit code) of the process.
+    pub status: ExitStatus,
+    /// The data that the process wrote to stdout.
+    pub stdout: Vec<u8>,
+    /// The data that the process wrote to stderr.
+    pub stderr: Vec<u8>,
+    /// Whether the data of stdout or stderr exceeded the limit and is
+    /// truncated.
+    pub truncated: bool,
+}
+
+impl From<LimitedOutput> for Output {
+    fn from(output: LimitedOutput) -> Output {
+        Output {
+            status: output.status,
+            stdout: output.stdout,
+            stderr: output.stderr,
+        }
+    }
+}
+
+// the data read from a pipe
+pub(crate) struct Captured {
+    pub(crate) data: Vec<u8>,
+    pub(crate) truncated: bool,
+}
+
+// read the data of a pipe in a new coroutine, the result is
+// sent to `tx` together with the index of the pipe
+pub(crate) fn capture<R: Read + Send + 'static>(
+    mut io: R,
+    limit: Option<OutputLimit>,
+    index: usize,
+    tx: Sender<(usize, io::Result<Captured>)>,
+) {
+    may::go!(move || {
+        let ret = match limit {
+            None => {
+                let mut data = Vec::new();
+                io.read_to_end(&mut data).map(|_| Captured {
+                    data,
+                    truncated: false,
+                })
+            }
+            Some(limit) => read_limited(&mut io, limit),
+        };
+        let _ = tx.send((index, ret));
+    });
+}
+
+fn read_limited<R: Read>(io: &mut R, limit: OutputLimit) -> io::Result<Captured> {
+    let mut data = Vec::new();
+    let mut truncated = false;
+    let mut buf = [0; 8192];
+    loop {
+        let n = match io.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if data.len() + n <= limit.max {
+            data.extend_from_slice(&buf[..n]);
+            continue;
+        }
+
+        truncated = true;
+        match limit.overflow {
+            Overflow::KeepTail => {
+                data.extend_from_slice(&buf[..n]);
+                // drop the head in batches to not move the data too often
+                if data.len() >= limit.max * 2 {
+                    data.drain(..data.len() - limit.max);
+                }
+            }
+            _ => {
+                let room = limit.max - data.len();
+                data.extend_from_slice(&buf[..room]);
+                if limit.kills() {
+                    break;
+                }
+            }
+        }
+    }
+    if data.len() > limit.max {
+        data.drain(..data.len() - limit.max);
+    }
+    Ok(Captured { data, truncated })
+}
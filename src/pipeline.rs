@@ -0,0 +1,323 @@
+//! Pipelines of processes, like `a | b | c` in the shell
+//!
+//! The stdout of each stage is connected to the stdin of the next one with
+//! an OS pipe, so the data flows between the children directly without
+//! passing through the parent.
+//!
+
+use std::fmt;
+use std::io;
+use std::process::{ExitStatus, Stdio};
+
+use may::sync::mpsc::channel;
+
+use crate::limit::{self, Overflow};
+use crate::{Child, Command};
+
+/// A pipeline of commands, where the stdout of each command is connected to
+/// the stdin of the next one.
+///
+/// The stdin of the first command and the stdout of the last command, as
+/// well as the stderr of every command, are configured by the commands
+/// themselves, the same as when they are spawned alone.
+///
+/// # Examples
+///
+/// ```no_run
+/// use may_process::{Command, Pipeline};
+///
+/// let mut grep = Command::new("grep");
+/// grep.arg("may");
+///
+/// let output = Pipeline::new()
+///                       .pipe(Command::new("ls"))
+///                       .pipe(grep)
+///                       .pipe(Command::new("sort"))
+///                       .output()
+///                       .expect("failed to execute pipeline");
+///
+/// assert!(output.status.success());
+/// println!("{}", String::from_utf8_lossy(&output.stdout));
+/// ```
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    commands: Vec<Command>,
+}
+
+impl Pipeline {
+    /// Constructs a new empty `Pipeline`.
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// Appends a command to the end of the pipeline.
+    pub fn pipe(&mut self, command: Command) -> &mut Pipeline {
+        self.commands.push(command);
+        self
+    }
+
+    /// Executes all the commands of the pipeline as child processes,
+    /// returning handles to them.
+    ///
+    /// If any of the commands fails to spawn, the children that are already
+    /// spawned are killed and reaped before the error is returned.
+    ///
+    /// An error of kind `InvalidInput` is returned if the pipeline is empty,
+    /// or if a command would replace the pipe to the previous stage, which
+    /// is the case for `input_from` on any stage but the first one, and for
+    /// `pty` on any stage.
+    pub fn spawn(&mut self) -> io::Result<PipelineChild> {
+        if self.commands.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "empty pipeline",
+            ));
+        }
+        for (i, cmd) in self.commands.iter().enumerate() {
+            if i > 0 && cmd.input.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "only the first stage of a pipeline can read an input",
+                ));
+            }
+            #[cfg(target_os = "linux")]
+            if cmd.pty.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a pipeline stage can't run on a pty",
+                ));
+            }
+        }
+
+        let last = self.commands.len() - 1;
+        let mut children = Vec::with_capacity(self.commands.len());
+        let mut next_stdin = None;
+        for (i, cmd) in self.commands.iter_mut().enumerate() {
+            let ret = link(cmd, next_stdin.take(), i < last).and_then(|stdin| {
+                next_stdin = stdin;
+                cmd.spawn_inner()
+            });
+            // the pipe ends are owned by the children now, don't keep
+            // them open in the command or the readers never see EOF
+            if i > 0 {
+                cmd.inner.stdin(Stdio::null());
+            }
+            if i < last {
+                cmd.inner.stdout(Stdio::null());
+            }
+            match ret {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    for mut child in children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(PipelineChild { children })
+    }
+
+    /// Executes the pipeline, waiting for all the children to finish and
+    /// collecting their statuses.
+    pub fn status(&mut self) -> io::Result<PipelineStatus> {
+        self.spawn().and_then(|mut p| p.wait())
+    }
+
+    /// Executes the pipeline, waiting for all the children to finish and
+    /// collecting the stdout of the last stage and the stderr of every
+    /// stage.
+    ///
+    /// The stdin of the first stage is null, the same as with
+    /// `Command::output`.
+    pub fn output(&mut self) -> io::Result<PipelineOutput> {
+        if let Some(first) = self.commands.first_mut() {
+            first.inner.stdin(Stdio::null());
+        }
+        if let Some(last) = self.commands.last_mut() {
+            last.inner.stdout(Stdio::piped());
+        }
+        for cmd in &mut self.commands {
+            cmd.inner.stderr(Stdio::piped());
+        }
+        self.spawn().and_then(|p| p.wait_with_output())
+    }
+}
+
+// connect the command to the previous stage, returns the read end
+// of the pipe that feeds the next stage if there is one
+fn link(cmd: &mut Command, stdin: Option<Stdio>, piped: bool) -> io::Result<Option<Stdio>> {
+    if let Some(stdin) = stdin {
+        cmd.inner.stdin(stdin);
+    }
+    if !piped {
+        return Ok(None);
+    }
+    let (reader, writer) = io::pipe()?;
+    cmd.inner.stdout(writer);
+    Ok(Some(reader.into()))
+}
+
+/// The running children of a [`Pipeline`], in the order of the stages.
+///
+/// [`Pipeline`]: struct.Pipeline.html
+#[derive(Debug)]
+pub struct PipelineChild {
+    children: Vec<Child>,
+}
+
+impl PipelineChild {
+    /// Returns the children of the pipeline.
+    pub fn children(&mut self) -> &mut [Child] {
+        &mut self.children
+    }
+
+    /// Kills all the children of the pipeline.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.children.iter_mut().try_for_each(Child::kill)
+    }
+
+    /// Waits for all the children to exit, returning their statuses.
+    pub fn wait(&mut self) -> io::Result<PipelineStatus> {
+        let statuses = self
+            .children
+            .iter_mut()
+            .map(Child::wait)
+            .collect::<io::Result<_>>()?;
+        Ok(PipelineStatus { statuses })
+    }
+
+    /// Waits for all the children to exit, collecting the remaining stdout
+    /// of the last stage and the stderr of every stage that is captured.
+    ///
+    /// Each stream is collected with the [`max_output`] of its stage. With
+    /// [`Overflow::Error`] all the children are killed and an error is
+    /// returned, with [`Overflow::Kill`] only that stage is killed.
+    ///
+    /// [`max_output`]: struct.Command.html#method.max_output
+    /// [`Overflow::Error`]: enum.Overflow.html#variant.Error
+    /// [`Overflow::Kill`]: enum.Overflow.html#variant.Kill
+    pub fn wait_with_output(mut self) -> io::Result<PipelineOutput> {
+        // the stdout of the last stage is pipe 0, the stderr of stage i is
+        // pipe i + 1, all of them are drained concurrently
+        let (tx, rx) = channel();
+        let last = self.children.len() - 1;
+        let mut pending = 0;
+        if let Some(io) = self.children[last].take_stdout() {
+            limit::capture(io, self.children[last].max_output, 0, tx.clone());
+            pending += 1;
+        }
+        for (i, child) in self.children.iter_mut().enumerate() {
+            if let Some(io) = child.take_stderr() {
+                limit::capture(io, child.max_output, i + 1, tx.clone());
+                pending += 1;
+            }
+        }
+        drop(tx);
+
+        let mut stdout = Vec::new();
+        let mut stderr = vec![Vec::new(); self.children.len()];
+        let mut truncated = false;
+        for _ in 0..pending {
+            let (i, captured) = rx
+                .recv()
+                .map_err(|_| io::Error::other("pipe reader panicked"))?;
+            let captured = captured?;
+            let stage = if i == 0 { last } else { i - 1 };
+            if captured.truncated {
+                truncated = true;
+                match self.children[stage].max_output {
+                    Some(limit) if limit.overflow == Overflow::Error => {
+                        for child in &mut self.children {
+                            let _ = child.kill();
+                        }
+                        self.wait()?;
+                        return Err(io::Error::other("process output exceeds the limit"));
+                    }
+                    Some(limit) if limit.kills() => self.children[stage].kill()?,
+                    _ => {}
+                }
+            }
+            if i == 0 {
+                stdout = captured.data;
+            } else {
+                stderr[i - 1] = captured.data;
+            }
+        }
+
+        let status = self.wait()?;
+        Ok(PipelineOutput {
+            status,
+            stdout,
+            stderr,
+            truncated,
+        })
+    }
+}
+
+/// The exit statuses of all the stages of a finished [`Pipeline`].
+///
+/// [`Pipeline`]: struct.Pipeline.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipelineStatus {
+    statuses: Vec<ExitStatus>,
+}
+
+impl PipelineStatus {
+    /// Returns the statuses of the stages, in order.
+    pub fn statuses(&self) -> &[ExitStatus] {
+        &self.statuses
+    }
+
+    /// Returns the status of the last stage, which is the status of the
+    /// pipeline in the shell by default.
+    pub fn last(&self) -> ExitStatus {
+        *self.statuses.last().expect("empty pipeline")
+    }
+
+    /// Returns the status of the last stage that failed, or the status of
+    /// the last stage if all of them succeeded, like `set -o pipefail` in
+    /// the shell.
+    pub fn pipefail(&self) -> ExitStatus {
+        self.statuses
+            .iter()
+            .rev()
+            .find(|s| !s.success())
+            .copied()
+            .unwrap_or_else(|| self.last())
+    }
+
+    /// Returns `true` if all the stages succeeded.
+    pub fn success(&self) -> bool {
+        self.statuses.iter().all(ExitStatus::success)
+    }
+}
+
+impl fmt::Display for PipelineStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, status) in self.statuses.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" | ")?;
+            }
+            write!(f, "{}", status)?;
+        }
+        Ok(())
+    }
+}
+
+/// The output of a finished [`Pipeline`].
+///
+/// [`Pipeline`]: struct.Pipeline.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipelineOutput {
+    /// The statuses of all the stages.
+    pub status: PipelineStatus,
+    /// The data that the last stage wrote to stdout.
+    pub stdout: Vec<u8>,
+    /// The data that each stage wrote to stderr, in the order of the stages.
+    pub stderr: Vec<Vec<u8>>,
+    /// Whether any of the data exceeded the output limit of its stage and
+    /// is truncated.
+    pub truncated: bool,
+}
@@ -0,0 +1,237 @@
+//! Running many commands with a bounded concurrency
+//!
+//! Every submitted job runs in its own coroutine, which takes a permit of
+//! a shared semaphore before spawning the child, so at most the configured
+//! number of children run at once while the rest of the coroutines are
+//! parked cheaply. The results are sent to a channel as the jobs finish.
+//!
+//! The coroutines of the unfinished jobs are shared with the jobs as well,
+//! so with fail fast the failing job cancels the others itself, instead of
+//! waiting for its result to be taken from the pool.
+//!
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::process::Output;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use may::coroutine::{Coroutine, JoinHandle};
+use may::sync::mpsc::{channel, Receiver, Sender};
+use may::sync::Semphore;
+
+use crate::Command;
+
+/// A pool running commands as child processes, with at most a given
+/// number of them at once.
+///
+/// The pool is an iterator over the results of the jobs in the order that
+/// they finish, each with the id returned by [`submit`]. The iteration
+/// ends once all the submitted jobs are done or cancelled.
+///
+/// The output of each job is collected as with [`Command::output`].
+///
+/// Dropping the pool cancels the jobs that are not done yet, see
+/// [`cancel`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use may_process::{Command, ProcessPool};
+///
+/// let mut pool = ProcessPool::new(8);
+/// for file in &["a.c", "b.c", "c.c"] {
+///     let mut cmd = Command::new("cc");
+///     cmd.args(&["-c", file]);
+///     pool.submit(cmd);
+/// }
+///
+/// for (job, output) in &mut pool {
+///     let output = output.expect("failed to run the compiler");
+///     println!("job {} finished with {}", job, output.status);
+/// }
+/// ```
+///
+/// [`submit`]: #method.submit
+/// [`cancel`]: #method.cancel
+/// [`Command::output`]: struct.Command.html#method.output
+pub struct ProcessPool {
+    permits: Arc<Semphore>,
+    // the coroutines of the jobs whose results are not taken yet
+    jobs: HashMap<usize, JoinHandle<()>>,
+    // the coroutines of the jobs that are not finished yet
+    running: Arc<Mutex<HashMap<usize, Coroutine>>>,
+    next: usize,
+    tx: Sender<Done>,
+    rx: Receiver<Done>,
+    fail_fast: Arc<AtomicBool>,
+}
+
+impl ProcessPool {
+    /// Creates an empty pool running at most `limit` children at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    pub fn new(limit: usize) -> ProcessPool {
+        assert!(limit > 0, "the concurrency limit must not be zero");
+        let (tx, rx) = channel();
+        ProcessPool {
+            permits: Arc::new(Semphore::new(limit)),
+            jobs: HashMap::new(),
+            running: Arc::new(Mutex::new(HashMap::new())),
+            next: 0,
+            tx,
+            rx,
+            fail_fast: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Cancels all the remaining jobs once a job fails, that is when it
+    /// can't be run or doesn't exit successfully.
+    ///
+    /// The failing job cancels the other jobs of the pool as soon as it
+    /// finishes, as with [`cancel`], even if its result is not taken yet.
+    /// The result of the failed job is still returned, and it's the last
+    /// one.
+    ///
+    /// [`cancel`]: #method.cancel
+    pub fn fail_fast(&mut self, fail_fast: bool) -> &mut ProcessPool {
+        self.fail_fast.store(fail_fast, Ordering::SeqCst);
+        self
+    }
+
+    /// Adds a job running `command` to the pool, returning the id of the
+    /// job.
+    ///
+    /// The job starts right away if fewer children than the limit are
+    /// running, otherwise it waits for a running job to finish. The ids
+    /// count up from zero in the order of submission.
+    pub fn submit(&mut self, mut command: Command) -> usize {
+        let id = self.next;
+        self.next += 1;
+        // a cancelled job kills its child
+        command.kill_on_cancel(true);
+        let permits = self.permits.clone();
+        let running = self.running.clone();
+        let fail_fast = self.fail_fast.clone();
+        let tx = self.tx.clone();
+        let job = may::go!(move || {
+            permits.wait();
+            let permit = Permit(&permits);
+            let ret = command.output();
+            let failed = !matches!(ret, Ok(ref o) if o.status.success());
+            let cancel_others = failed && fail_fast.load(Ordering::SeqCst);
+            let others: Vec<Coroutine> = {
+                let mut jobs = running.lock().unwrap();
+                jobs.remove(&id);
+                if cancel_others {
+                    jobs.drain().map(|(_, job)| job).collect()
+                } else {
+                    Vec::new()
+                }
+            };
+            // before the permit is given back, so no queued job starts
+            for job in others {
+                unsafe { job.cancel() };
+            }
+            drop(permit);
+            let _ = tx.send(Done {
+                id,
+                ret,
+                cancel_others,
+            });
+        });
+        // a job that is submitted while another one fails may miss the
+        // cancellation, it's cancelled once the failure is taken then
+        let coroutine = job.coroutine().clone();
+        self.running.lock().unwrap().insert(id, coroutine);
+        self.jobs.insert(id, job);
+        id
+    }
+
+    /// Returns the number of jobs whose results are not taken yet.
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Returns `true` if all the submitted jobs are done or cancelled.
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Cancels all the jobs whose results are not taken yet.
+    ///
+    /// The children of the running jobs are killed before this returns,
+    /// and reaped in the background once they have exited. The results of
+    /// the cancelled jobs are never returned.
+    pub fn cancel(&mut self) {
+        self.running.lock().unwrap().clear();
+        for (_, job) in self.jobs.drain() {
+            if !job.is_done() {
+                unsafe { job.coroutine().cancel() };
+            }
+            // a cancelled job panics
+            let _ = job.join();
+        }
+    }
+}
+
+impl Iterator for ProcessPool {
+    type Item = (usize, io::Result<Output>);
+
+    /// Waits for the next job to finish, returning its id and output.
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.jobs.is_empty() {
+            // the pool holds a sender, so the channel is never disconnected
+            let done = self.rx.recv().expect("process pool channel closed");
+            // a job may finish right before it's cancelled
+            let job = match self.jobs.remove(&done.id) {
+                Some(job) => job,
+                None => continue,
+            };
+            let _ = job.join();
+            self.running.lock().unwrap().remove(&done.id);
+            // the cancelled jobs never send their results, and the results
+            // the others sent meanwhile are dropped
+            if done.cancel_others {
+                self.cancel();
+            }
+            return Some((done.id, done.ret));
+        }
+        None
+    }
+}
+
+impl Drop for ProcessPool {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+impl fmt::Debug for ProcessPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProcessPool")
+            .field("jobs", &self.jobs.len())
+            .field("fail_fast", &self.fail_fast.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+// the result of a finished job
+struct Done {
+    id: usize,
+    ret: io::Result<Output>,
+    // the job failed and cancelled the others for fail fast
+    cancel_others: bool,
+}
+
+// gives the permit back, also when the job is cancelled
+struct Permit<'a>(&'a Semphore);
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        self.0.post();
+    }
+}
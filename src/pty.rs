@@ -0,0 +1,215 @@
+//! Pseudo-terminal support on Linux
+//!
+//! The child gets the slave side of a new pty as its stdin, stdout and
+//! stderr. Right before executing the program it starts a new session and
+//! makes the slave its controlling terminal, so the program behaves as if
+//! it's run from an interactive terminal. The parent talks to it through
+//! the master side, which is registered to the may event loop.
+//!
+//! Once all the processes on the slave side have exited, reading from the
+//! master fails with `EIO`, which is reported as the end of the stream.
+//!
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::prelude::*;
+use std::process::{self, Stdio};
+
+use crate::imp::{ArmedFd, PreExecFd};
+use crate::stdio::Pipe;
+
+/// The configuration of the pseudo-terminal of a child process.
+///
+/// # Examples
+///
+/// ```no_run
+/// use may_process::PtyConfig;
+///
+/// let config = PtyConfig::new().size(50, 132);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PtyConfig {
+    rows: u16,
+    cols: u16,
+}
+
+impl PtyConfig {
+    /// Creates a configuration of a terminal with 24 rows and 80 columns.
+    pub fn new() -> PtyConfig {
+        PtyConfig { rows: 24, cols: 80 }
+    }
+
+    /// Sets the initial size of the terminal.
+    pub fn size(mut self, rows: u16, cols: u16) -> PtyConfig {
+        self.rows = rows;
+        self.cols = cols;
+        self
+    }
+}
+
+impl Default for PtyConfig {
+    fn default() -> PtyConfig {
+        PtyConfig::new()
+    }
+}
+
+/// The master side of the pseudo-terminal of a child process.
+///
+/// Reading from it returns what the child writes to the terminal, and
+/// writing to it is like typing into the terminal. Reading or writing in
+/// coroutine context will not block the worker thread.
+///
+/// Once the child and all the other processes using the terminal have
+/// exited, reading returns `Ok(0)`.
+///
+/// This struct is returned by [`Child::pty`] and [`Child::take_pty`].
+///
+/// [`Child::pty`]: struct.Child.html#method.pty
+/// [`Child::take_pty`]: struct.Child.html#method.take_pty
+pub struct PtyMaster {
+    inner: Pipe<File>,
+}
+
+impl PtyMaster {
+    /// Creates a new handle to the master side, so it can be read and
+    /// written from different coroutines.
+    pub fn try_clone(&self) -> io::Result<PtyMaster> {
+        Ok(PtyMaster {
+            inner: Pipe::new(self.inner.inner().try_clone()?),
+        })
+    }
+
+    /// Changes the size of the terminal, the processes on it receive a
+    /// `SIGWINCH`.
+    pub fn resize(&self, rows: u16, cols: u16) -> io::Result<()> {
+        let size = winsize(rows, cols);
+        let fd = self.as_raw_fd();
+        if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Read for PtyMaster {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            // the slave side is closed
+            Err(ref e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            ret => ret,
+        }
+    }
+}
+
+impl Write for PtyMaster {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl AsRawFd for PtyMaster {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.inner().as_raw_fd()
+    }
+}
+
+impl fmt::Debug for PtyMaster {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("PtyMaster { .. }")
+    }
+}
+
+// the pty setup of a command
+pub(crate) struct Pty {
+    config: PtyConfig,
+    // the slave of the next child
+    slave: PreExecFd,
+}
+
+// keeps the pty open until the child is spawned
+pub(crate) struct Opened {
+    master: File,
+    // dropped before the slave is closed
+    _armed: ArmedFd,
+    _slave: File,
+}
+
+impl Opened {
+    pub(crate) fn into_master(self) -> PtyMaster {
+        PtyMaster {
+            inner: Pipe::new(self.master),
+        }
+    }
+}
+
+impl Pty {
+    // set the pty of a command
+    pub(crate) fn set(pty: &mut Option<Pty>, cmd: &mut process::Command, config: PtyConfig) {
+        if let Some(ref mut p) = *pty {
+            p.config = config;
+            return;
+        }
+
+        // make the slave the controlling terminal of a new session
+        let slave = unsafe {
+            PreExecFd::new(cmd, -1, |fd| match libc::setsid() {
+                -1 => -1,
+                _ => libc::ioctl(fd, libc::TIOCSCTTY, 0),
+            })
+        };
+        *pty = Some(Pty { config, slave });
+    }
+
+    // open a new pty and set up its slave as the stdio of the next child
+    pub(crate) fn open(&self, cmd: &mut process::Command) -> io::Result<Opened> {
+        let (mut master, mut slave) = (-1, -1);
+        let size = winsize(self.config.rows, self.config.cols);
+        let (name, termp) = (std::ptr::null_mut(), std::ptr::null());
+        if unsafe { libc::openpty(&mut master, &mut slave, name, termp, &size) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = unsafe { File::from_raw_fd(master) };
+        let slave = unsafe { File::from_raw_fd(slave) };
+        // other children must not inherit them
+        cloexec(&master)?;
+        cloexec(&slave)?;
+
+        cmd.stdin(slave.try_clone()?)
+            .stdout(slave.try_clone()?)
+            .stderr(slave.try_clone()?);
+        Ok(Opened {
+            master,
+            _armed: self.slave.arm(slave.as_raw_fd()),
+            _slave: slave,
+        })
+    }
+
+    // drop the slave from the command, or the master never sees the end
+    pub(crate) fn close(cmd: &mut process::Command) {
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+    }
+}
+
+fn winsize(rows: u16, cols: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+fn cloexec(file: &File) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,145 @@
+//! Waiting for any of a set of child processes
+//!
+//! Every member of a `ChildSet` asks the reaper to send its token to the
+//! channel of the set once it's reaped, so a single receive services the
+//! whole set no matter how many children it holds.
+//!
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+use may::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+
+use crate::Child;
+
+/// A collection of child processes that can be waited for as a whole.
+///
+/// Each child is inserted with a key of the caller's choice, and
+/// [`wait_any`] returns the key of whichever child exits first. Waiting
+/// on the set from a coroutine only parks the coroutine, and the exit of
+/// any member wakes it up once.
+///
+/// This type is only available on unix.
+///
+/// # Examples
+///
+/// ```no_run
+/// use may_process::{ChildSet, Command};
+///
+/// let mut set = ChildSet::new();
+/// for job in &["a", "b", "c"] {
+///     let child = Command::new("make").arg(job).spawn().unwrap();
+///     set.insert(*job, child);
+/// }
+///
+/// while let Some((job, status)) = set.wait_any().unwrap() {
+///     println!("{} finished with {}", job, status);
+/// }
+/// ```
+///
+/// [`wait_any`]: #method.wait_any
+pub struct ChildSet<K> {
+    children: HashMap<usize, (K, Child)>,
+    tx: Sender<usize>,
+    rx: Receiver<usize>,
+    // the token of the next inserted child
+    next: usize,
+}
+
+impl<K> ChildSet<K> {
+    /// Creates an empty `ChildSet`.
+    pub fn new() -> ChildSet<K> {
+        let (tx, rx) = channel();
+        ChildSet {
+            children: HashMap::new(),
+            tx,
+            rx,
+            next: 0,
+        }
+    }
+
+    /// Adds a child to the set under `key`.
+    ///
+    /// Keys don't need to be unique, they are only handed back when the
+    /// child exits.
+    pub fn insert(&mut self, key: K, mut child: Child) {
+        let token = self.next;
+        self.next += 1;
+        child.inner.notify(self.tx.clone(), token);
+        self.children.insert(token, (key, child));
+    }
+
+    /// Returns the number of children in the set.
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Returns `true` if there is no child in the set.
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Returns an iterator over the keys and children in the set, e.g. to
+    /// kill them all.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut Child)> {
+        self.children.values_mut().map(|(k, c)| (&*k, c))
+    }
+
+    /// Waits for any child in the set to exit, removes it from the set and
+    /// returns its key together with the status that it exited with.
+    ///
+    /// Returns `Ok(None)` if the set is empty.
+    pub fn wait_any(&mut self) -> io::Result<Option<(K, ExitStatus)>> {
+        self.wait_any_inner(None)
+    }
+
+    /// Waits for any child in the set to exit for at most `timeout`, the
+    /// same as [`wait_any`].
+    ///
+    /// Returns `Ok(None)` if the set is empty or no child exited before the
+    /// timeout.
+    ///
+    /// [`wait_any`]: #method.wait_any
+    pub fn wait_any_timeout(&mut self, timeout: Duration) -> io::Result<Option<(K, ExitStatus)>> {
+        self.wait_any_inner(Some(Instant::now() + timeout))
+    }
+
+    fn wait_any_inner(&mut self, deadline: Option<Instant>) -> io::Result<Option<(K, ExitStatus)>> {
+        if self.children.is_empty() {
+            return Ok(None);
+        }
+        let token = match deadline {
+            // the set holds a sender, so the channel is never disconnected
+            None => self.rx.recv().expect("child set channel closed"),
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match self.rx.recv_timeout(timeout) {
+                    Ok(token) => token,
+                    Err(RecvTimeoutError::Timeout) => return Ok(None),
+                    Err(RecvTimeoutError::Disconnected) => unreachable!(),
+                }
+            }
+        };
+        let (key, mut child) = self.children.remove(&token).expect("unknown child token");
+        // the child is reaped already, so this doesn't block
+        let status = child.wait()?;
+        Ok(Some((key, status)))
+    }
+}
+
+impl<K> Default for ChildSet<K> {
+    fn default() -> ChildSet<K> {
+        ChildSet::new()
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for ChildSet<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(self.children.values().map(|(k, c)| (k, c)))
+            .finish()
+    }
+}
@@ -0,0 +1,278 @@
+//! Coroutine aware handles to the standard streams of a child process
+//!
+//! The pipes created by `std::process::Command` are plain blocking file
+//! descriptors (handles on Windows). We register them with the may event
+//! loop through `CoIo` so that reading or writing them from a coroutine
+//! only parks the coroutine instead of the whole worker thread.
+//!
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Split, Write};
+use std::process;
+
+use may::sync::mpsc::Sender;
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd as AsRaw, AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawHandle as AsRaw, AsRawHandle, RawHandle};
+
+use may::io::CoIo;
+
+pub(crate) enum Pipe<T: AsRaw> {
+    Co(CoIo<T>),
+    // if the remote pipe is closed, MacOs would return libc::EPIPE
+    // when register the fd to the event loop, so we fall back to
+    // the blocking io object in that case
+    Blocking(T),
+}
+
+impl<T: AsRaw> Pipe<T> {
+    pub(crate) fn new(io: T) -> Self {
+        match CoIo::new(io) {
+            Ok(io) => Pipe::Co(io),
+            Err(e) => Pipe::Blocking(e.into_data()),
+        }
+    }
+
+    pub(crate) fn inner(&self) -> &T {
+        match *self {
+            Pipe::Co(ref io) => io.inner(),
+            Pipe::Blocking(ref io) => io,
+        }
+    }
+}
+
+impl<T: AsRaw + Read> Read for Pipe<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Pipe::Co(ref mut io) => io.read(buf),
+            Pipe::Blocking(ref mut io) => io.read(buf),
+        }
+    }
+}
+
+impl<T: AsRaw + Write> Write for Pipe<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Pipe::Co(ref mut io) => io.write(buf),
+            Pipe::Blocking(ref mut io) => io.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Pipe::Co(ref mut io) => io.flush(),
+            Pipe::Blocking(ref mut io) => io.flush(),
+        }
+    }
+}
+
+// read the data of a pipe in a new coroutine, sending each chunk of it to
+// `tx` as `wrap(chunk)`, a read error ends the reading and is sent as well
+pub(crate) fn read_chunks<R, T, F>(mut io: R, tx: Sender<io::Result<T>>, wrap: F)
+where
+    R: Read + Send + 'static,
+    T: Send + 'static,
+    F: Fn(Vec<u8>) -> T + Send + 'static,
+{
+    may::go!(move || {
+        let mut buf = vec![0; 8192];
+        loop {
+            match io.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    // the receiver is gone
+                    if tx.send(Ok(wrap(buf[..n].to_vec()))).is_err() {
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// A handle to a child process's standard input (stdin).
+///
+/// This struct is returned by [`Child::stdin`] and [`Child::take_stdin`].
+///
+/// When an instance of `ChildStdin` is [dropped], the `ChildStdin`'s underlying
+/// file handle will be closed. If the child process was blocked on input prior
+/// to being dropped, it will become unblocked after dropping.
+///
+/// Writing to it in coroutine context will not block the worker thread.
+///
+/// [`Child::stdin`]: struct.Child.html#method.stdin
+/// [`Child::take_stdin`]: struct.Child.html#method.take_stdin
+/// [dropped]: https://doc.rust-lang.org/std/ops/trait.Drop.html
+pub struct ChildStdin {
+    inner: Pipe<process::ChildStdin>,
+}
+
+impl ChildStdin {
+    pub(crate) fn new(io: process::ChildStdin) -> ChildStdin {
+        ChildStdin {
+            inner: Pipe::new(io),
+        }
+    }
+}
+
+impl Write for ChildStdin {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl fmt::Debug for ChildStdin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("ChildStdin { .. }")
+    }
+}
+
+/// A handle to a child process's standard output (stdout).
+///
+/// This struct is returned by [`Child::stdout`] and [`Child::take_stdout`].
+///
+/// When an instance of `ChildStdout` is [dropped], the `ChildStdout`'s
+/// underlying file handle will be closed.
+///
+/// Reading from it in coroutine context will not block the worker thread.
+///
+/// [`Child::stdout`]: struct.Child.html#method.stdout
+/// [`Child::take_stdout`]: struct.Child.html#method.take_stdout
+/// [dropped]: https://doc.rust-lang.org/std/ops/trait.Drop.html
+pub struct ChildStdout {
+    inner: Pipe<process::ChildStdout>,
+}
+
+impl ChildStdout {
+    pub(crate) fn new(io: process::ChildStdout) -> ChildStdout {
+        ChildStdout {
+            inner: Pipe::new(io),
+        }
+    }
+}
+
+impl Read for ChildStdout {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl fmt::Debug for ChildStdout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("ChildStdout { .. }")
+    }
+}
+
+/// A handle to a child process's stderr.
+///
+/// This struct is returned by [`Child::stderr`] and [`Child::take_stderr`].
+///
+/// When an instance of `ChildStderr` is [dropped], the `ChildStderr`'s
+/// underlying file handle will be closed.
+///
+/// Reading from it in coroutine context will not block the worker thread.
+///
+/// [`Child::stderr`]: struct.Child.html#method.stderr
+/// [`Child::take_stderr`]: struct.Child.html#method.take_stderr
+/// [dropped]: https://doc.rust-lang.org/std/ops/trait.Drop.html
+pub struct ChildStderr {
+    inner: Pipe<process::ChildStderr>,
+}
+
+impl ChildStderr {
+    pub(crate) fn new(io: process::ChildStderr) -> ChildStderr {
+        ChildStderr {
+            inner: Pipe::new(io),
+        }
+    }
+}
+
+impl Read for ChildStderr {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl fmt::Debug for ChildStderr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("ChildStderr { .. }")
+    }
+}
+
+macro_rules! impl_lines {
+    ($($t:ident: $name:ident),*) => {$(
+        impl $t {
+            /// Returns an iterator over the lines of the stream, as they are
+            /// written by the child.
+            ///
+            /// The lines are returned without the trailing newline or CRLF.
+            /// Iterating in coroutine context only blocks the coroutine.
+            ///
+            /// # Examples
+            ///
+            /// ```no_run
+            /// use may_process::Command;
+            /// use std::process::Stdio;
+            ///
+            /// let mut child = Command::new("ping")
+            ///     .arg("localhost")
+            #[doc = concat!("    .", stringify!($name), "(Stdio::piped())")]
+            ///     .spawn()
+            ///     .unwrap();
+            ///
+            #[doc = concat!(" for line in child.take_", stringify!($name), "().unwrap().lines() {")]
+            ///     println!("{}", line.unwrap());
+            /// }
+            /// ```
+            pub fn lines(self) -> io::Lines<BufReader<$t>> {
+                BufReader::new(self).lines()
+            }
+
+            /// Returns an iterator over the contents of the stream split on
+            /// the byte `delim`, as they are written by the child.
+            ///
+            /// The returned chunks don't contain the delimiter. This is handy
+            /// for output separated by `\0`, e.g. from `find -print0`.
+            pub fn split(self, delim: u8) -> Split<BufReader<$t>> {
+                BufReader::new(self).split(delim)
+            }
+        }
+    )*};
+}
+
+impl_lines!(ChildStdout: stdout, ChildStderr: stderr);
+
+#[cfg(unix)]
+macro_rules! impl_as_raw {
+    ($($t:ty),*) => {$(
+        impl AsRawFd for $t {
+            fn as_raw_fd(&self) -> RawFd {
+                self.inner.inner().as_raw_fd()
+            }
+        }
+    )*};
+}
+
+#[cfg(windows)]
+macro_rules! impl_as_raw {
+    ($($t:ty),*) => {$(
+        impl AsRawHandle for $t {
+            fn as_raw_handle(&self) -> RawHandle {
+                self.inner.inner().as_raw_handle()
+            }
+        }
+    )*};
+}
+
+impl_as_raw!(ChildStdin, ChildStdout, ChildStderr);
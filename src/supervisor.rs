@@ -0,0 +1,285 @@
+//! Keeping a child process running with restart policies
+//!
+//! The supervisor runs in its own coroutine. The reaper sends a token to
+//! the channel of the supervisor once the child exits, and a stop request
+//! sends another token to the same channel, so a single receive waits for
+//! whichever comes first, also during the backoff between restarts.
+//!
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+use may::coroutine::JoinHandle;
+use may::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+
+use crate::{Command, Termination};
+
+// the tokens sent to the channel of the supervisor
+const EXITED: usize = 0;
+const STOP: usize = 1;
+
+/// When a [`Supervisor`] restarts its child process.
+///
+/// [`Supervisor`]: struct.Supervisor.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RestartPolicy {
+    /// Restart the child whenever it exits.
+    Always,
+    /// Restart the child if it exits unsuccessfully, is killed by a signal
+    /// or fails to spawn.
+    OnFailure,
+    /// Never restart the child.
+    Never,
+}
+
+/// A change of the state of a supervised child process, received from
+/// [`SupervisorHandle::events`].
+///
+/// [`SupervisorHandle::events`]: struct.SupervisorHandle.html#method.events
+#[derive(Debug)]
+pub enum SupervisorEvent {
+    /// The child is spawned, with the process identifier.
+    Started(u32),
+    /// The child exited with the status.
+    Exited(ExitStatus),
+    /// The command failed to spawn.
+    SpawnFailed(io::Error),
+    /// The child is going to be restarted after the delay.
+    Restarting(Duration),
+    /// The child is not restarted any more, because of the restart policy
+    /// or the restart limit.
+    GaveUp,
+    /// The supervisor is stopped, with how the child ended if it was
+    /// running.
+    Stopped(Option<Termination>),
+}
+
+/// A supervisor that keeps a child process running, restarting it
+/// according to a policy when it exits.
+///
+/// Consecutive restarts are delayed with an exponential backoff, and the
+/// supervisor gives up once the child restarts too often. Its state
+/// changes are sent to a channel, see [`SupervisorEvent`].
+///
+/// This type is only available on unix.
+///
+/// # Examples
+///
+/// ```no_run
+/// use may_process::{Command, RestartPolicy, Supervisor};
+/// use std::time::Duration;
+///
+/// let mut cmd = Command::new("my-helper");
+/// cmd.arg("--serve");
+///
+/// let handle = Supervisor::new(cmd)
+///     .restart(RestartPolicy::OnFailure)
+///     .max_restarts(5, Duration::from_secs(60))
+///     .start();
+///
+/// for event in handle.events().iter().take(3) {
+///     println!("helper: {:?}", event);
+/// }
+/// handle.stop().expect("failed to stop the helper");
+/// ```
+///
+/// [`SupervisorEvent`]: enum.SupervisorEvent.html
+#[derive(Debug)]
+pub struct Supervisor {
+    command: Command,
+    restart: RestartPolicy,
+    max_restarts: Option<(usize, Duration)>,
+    backoff: (Duration, Duration),
+    grace_period: Duration,
+}
+
+impl Supervisor {
+    /// Constructs a new `Supervisor` of the child processes spawned from
+    /// `command`, with the following default configuration:
+    ///
+    /// * Restart the child whenever it exits
+    /// * No limit of the number of restarts
+    /// * A backoff from 100 milliseconds up to 30 seconds
+    /// * A grace period of 5 seconds when stopped
+    pub fn new(command: Command) -> Supervisor {
+        Supervisor {
+            command,
+            restart: RestartPolicy::Always,
+            max_restarts: None,
+            backoff: (Duration::from_millis(100), Duration::from_secs(30)),
+            grace_period: Duration::from_secs(5),
+        }
+    }
+
+    /// Sets when the child is restarted.
+    pub fn restart(mut self, policy: RestartPolicy) -> Supervisor {
+        self.restart = policy;
+        self
+    }
+
+    /// Gives up once the child is restarted `max` times within `window`.
+    pub fn max_restarts(mut self, max: usize, window: Duration) -> Supervisor {
+        self.max_restarts = Some((max, window));
+        self
+    }
+
+    /// Sets the backoff between restarts.
+    ///
+    /// The first restart is delayed by `initial`, and the delay doubles on
+    /// each further restart up to `max`. It's reset to `initial` once a
+    /// child has run for at least `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Supervisor {
+        self.backoff = (initial, max.max(initial));
+        self
+    }
+
+    /// Sets how long the child is given to exit after a `SIGTERM` when the
+    /// supervisor is stopped, before it's killed.
+    pub fn grace_period(mut self, grace_period: Duration) -> Supervisor {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Starts supervising in a new coroutine, which spawns the first child
+    /// right away.
+    pub fn start(self) -> SupervisorHandle {
+        let (tx, rx) = channel();
+        let (events_tx, events) = channel();
+        let notify = tx.clone();
+        let handle = may::go!(move || self.run(notify, rx, events_tx));
+        SupervisorHandle {
+            stop: tx,
+            events,
+            handle: Some(handle),
+        }
+    }
+
+    fn run(
+        mut self,
+        tx: Sender<usize>,
+        rx: Receiver<usize>,
+        events: Sender<SupervisorEvent>,
+    ) -> io::Result<Option<Termination>> {
+        let emit = |event| {
+            let _ = events.send(event);
+        };
+        let (initial, max) = self.backoff;
+        let mut delay = initial;
+        let mut restarts = VecDeque::new();
+        loop {
+            let started = Instant::now();
+            let failed = match self.command.spawn() {
+                Ok(mut child) => {
+                    emit(SupervisorEvent::Started(child.id()));
+                    child.inner.notify(tx.clone(), EXITED);
+                    // the supervisor holds a sender, so the channel is never disconnected
+                    if rx.recv().expect("supervisor channel closed") == STOP {
+                        let termination = child.terminate(self.grace_period)?;
+                        emit(SupervisorEvent::Stopped(Some(termination)));
+                        return Ok(Some(termination));
+                    }
+                    // the child is reaped already, so this doesn't block
+                    let status = child.wait()?;
+                    emit(SupervisorEvent::Exited(status));
+                    !status.success()
+                }
+                Err(e) => {
+                    emit(SupervisorEvent::SpawnFailed(e));
+                    true
+                }
+            };
+
+            let restart = match self.restart {
+                RestartPolicy::Always => true,
+                RestartPolicy::OnFailure => failed,
+                RestartPolicy::Never => false,
+            };
+            if !restart {
+                emit(SupervisorEvent::GaveUp);
+                return Ok(None);
+            }
+            let now = Instant::now();
+            if let Some((max_restarts, window)) = self.max_restarts {
+                while restarts.front().is_some_and(|t| now - *t > window) {
+                    restarts.pop_front();
+                }
+                if restarts.len() >= max_restarts {
+                    emit(SupervisorEvent::GaveUp);
+                    return Ok(None);
+                }
+            }
+            restarts.push_back(now);
+
+            if now - started >= max {
+                delay = initial;
+            }
+            emit(SupervisorEvent::Restarting(delay));
+            match rx.recv_timeout(delay) {
+                Err(RecvTimeoutError::Timeout) => delay = (delay * 2).min(max),
+                // only a stop request is sent while there is no child
+                _ => {
+                    emit(SupervisorEvent::Stopped(None));
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// A handle to a running [`Supervisor`].
+///
+/// Dropping the handle stops the supervisor in the background, the same
+/// as [`stop`] without waiting for it.
+///
+/// [`Supervisor`]: struct.Supervisor.html
+/// [`stop`]: #method.stop
+pub struct SupervisorHandle {
+    stop: Sender<usize>,
+    events: Receiver<SupervisorEvent>,
+    handle: Option<JoinHandle<io::Result<Option<Termination>>>>,
+}
+
+impl SupervisorHandle {
+    /// Returns the channel of the state changes of the supervised child.
+    ///
+    /// The channel is closed once the supervisor is done, either because
+    /// it gave up or it's stopped.
+    pub fn events(&self) -> &Receiver<SupervisorEvent> {
+        &self.events
+    }
+
+    /// Stops the supervisor, waiting for it to finish.
+    ///
+    /// If the child is running, it's terminated gracefully with a `SIGTERM`
+    /// and killed if it's still running after the grace period, see
+    /// [`Child::terminate`]. Returns how the child ended in that case, or
+    /// `None` if there was no running child.
+    ///
+    /// [`Child::terminate`]: struct.Child.html#method.terminate
+    pub fn stop(mut self) -> io::Result<Option<Termination>> {
+        // fails if the supervisor is done already
+        let _ = self.stop.send(STOP);
+        self.handle
+            .take()
+            .expect("supervisor already stopped")
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("supervisor panicked")))
+    }
+}
+
+impl Drop for SupervisorHandle {
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            let _ = self.stop.send(STOP);
+        }
+    }
+}
+
+impl fmt::Debug for SupervisorHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("SupervisorHandle { .. }")
+    }
+}
@@ -1,17 +1,28 @@
 //! Unix handling of child processes
 //!
 //! Right now the only "fancy" thing about this is how we implement the
-//! wait on `Child` to get the exit status. Unix offers no way to register
-//! a child with epoll, and the only real way to get a notification when a
-//! process exits is the SIGCHLD signal.
+//! wait on `Child` to get the exit status. Every spawned child is registered
+//! to a global reaper, which reaps it in the background once it exits and
+//! sends the exit status to a one-shot channel owned by the `Child`. Waiting
+//! for a child is then just receiving from that channel.
+//!
+//! On Linux 5.3+ a child can be referred to by a pidfd, which becomes
+//! readable once the process exits. The pidfd of each child is registered
+//! to the may event loop and waited for by its own coroutine, so the exit
+//! of a process only wakes up its own waiter. This strategy is the same as
+//! the one used on Windows with `RegisterWaitForSingleObject`.
+//!
+//! Elsewhere unix offers no way to register a child with epoll, and the only
+//! real way to get a notification when a process exits is the SIGCHLD signal.
 //!
 //! Signal handling in general is *super* hairy and complicated, and it's even
 //! more complicated here with the fact that signals are coalesced, so we may
 //! not get a SIGCHLD-per-child.
 //!
-//! Our best approximation here is to check *all spawned processes* for all
-//! SIGCHLD signals received. To do that we create a `Signal`, implemented in
-//! the `may_signal` crate, which is a stream over signals being received.
+//! Our best approximation here is to check *all registered processes* for all
+//! SIGCHLD signals received. To do that a background thread owns one `Signal`,
+//! implemented in the `may_signal` crate, which is a stream over signals being
+//! received. This way a SIGCHLD wakes up one thread instead of every waiter.
 //!
 
 #[doc(hiden)]
@@ -21,15 +32,268 @@ extern crate may_signal;
 
 use std::fmt;
 use std::io;
-use std::os::unix::prelude::*;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
 use std::process::{self, ExitStatus};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use may::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+
+/// A signal that can be sent to a child process.
+///
+/// Each named variant maps to the unix signal of the same name, any other
+/// signal is sent through [`Signal::from_raw`].
+///
+/// [`Signal::from_raw`]: #method.from_raw
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Signal {
+    /// `SIGHUP`, hangup of the controlling terminal.
+    Hup,
+    /// `SIGINT`, interrupt from the keyboard.
+    Int,
+    /// `SIGQUIT`, quit from the keyboard.
+    Quit,
+    /// `SIGKILL`, kill the process, can't be caught or ignored.
+    Kill,
+    /// `SIGTERM`, ask the process to terminate.
+    Term,
+    /// `SIGUSR1`, user-defined signal 1.
+    Usr1,
+    /// `SIGUSR2`, user-defined signal 2.
+    Usr2,
+    /// `SIGSTOP`, stop the process, can't be caught or ignored.
+    Stop,
+    /// `SIGCONT`, continue the process if it's stopped.
+    Cont,
+    /// Any other signal by its raw number. Use [`Signal::from_raw`] to
+    /// create it, so that the named signals compare equal to their
+    /// variants.
+    ///
+    /// [`Signal::from_raw`]: #method.from_raw
+    Other(libc::c_int),
+}
+
+impl Signal {
+    /// Creates a signal from its raw number, e.g. `libc::SIGWINCH`.
+    pub fn from_raw(sig: libc::c_int) -> Signal {
+        match sig {
+            libc::SIGHUP => Signal::Hup,
+            libc::SIGINT => Signal::Int,
+            libc::SIGQUIT => Signal::Quit,
+            libc::SIGKILL => Signal::Kill,
+            libc::SIGTERM => Signal::Term,
+            libc::SIGUSR1 => Signal::Usr1,
+            libc::SIGUSR2 => Signal::Usr2,
+            libc::SIGSTOP => Signal::Stop,
+            libc::SIGCONT => Signal::Cont,
+            sig => Signal::Other(sig),
+        }
+    }
+
+    /// Returns the raw signal number.
+    pub fn as_raw(self) -> libc::c_int {
+        match self {
+            Signal::Hup => libc::SIGHUP,
+            Signal::Int => libc::SIGINT,
+            Signal::Quit => libc::SIGQUIT,
+            Signal::Kill => libc::SIGKILL,
+            Signal::Term => libc::SIGTERM,
+            Signal::Usr1 => libc::SIGUSR1,
+            Signal::Usr2 => libc::SIGUSR2,
+            Signal::Stop => libc::SIGSTOP,
+            Signal::Cont => libc::SIGCONT,
+            Signal::Other(sig) => sig,
+        }
+    }
+}
+
+/// How a child process ended after [`Child::terminate`].
+///
+/// [`Child::terminate`]: struct.Child.html#method.terminate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// The child exited within the grace period, with the status.
+    Graceful(ExitStatus),
+    /// The child was still running after the grace period and had to be
+    /// killed, with the status.
+    Killed(ExitStatus),
+}
+
+impl Termination {
+    /// Returns the status that the child exited with.
+    pub fn status(&self) -> ExitStatus {
+        match *self {
+            Termination::Graceful(status) | Termination::Killed(status) => status,
+        }
+    }
+
+    /// Returns `true` if the child had to be killed.
+    pub fn killed(&self) -> bool {
+        matches!(*self, Termination::Killed(_))
+    }
+}
+
+/// The process group that a child process is spawned into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProcessGroup {
+    /// Create a new process group whose id is the pid of the child.
+    New,
+    /// Join the existing process group with the given id.
+    Join(u32),
+}
+
+// send a signal to all the processes in the process group, the id may be
+// reused once all of them are gone and reaped, see `Child::signal_group`
+pub fn signal_group(pgid: u32, sig: Signal) -> io::Result<()> {
+    if unsafe { libc::killpg(pgid as libc::pid_t, sig.as_raw()) } < 0 {
+        let err = io::Error::last_os_error();
+        // all the processes of the group have exited
+        if err.raw_os_error() == Some(libc::ESRCH) {
+            return Ok(());
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+// an fd that a `pre_exec` hook of a command uses right before the child
+// executes the program, -1 if there is none
+//
+// The hook runs in the child between `fork` and `exec`, where the other
+// threads of the parent are gone, possibly holding locks, so only
+// async-signal-safe calls are allowed there. The fd is shared through an
+// atomic so it can be changed for each child without any allocation or
+// lock in the hook.
+#[derive(Clone)]
+pub struct PreExecFd {
+    fd: Arc<AtomicI32>,
+}
+
+impl PreExecFd {
+    // install a `pre_exec` hook calling `hook` with the fd if there is one,
+    // a negative return of `hook` fails the spawn with the errno
+    //
+    // `hook` must only make async-signal-safe calls
+    pub unsafe fn new<F>(cmd: &mut process::Command, fd: RawFd, hook: F) -> PreExecFd
+    where
+        F: Fn(RawFd) -> libc::c_int + Send + Sync + 'static,
+    {
+        let slot = PreExecFd {
+            fd: Arc::new(AtomicI32::new(fd)),
+        };
+        let shared = slot.fd.clone();
+        cmd.pre_exec(move || {
+            let fd = shared.load(Ordering::SeqCst);
+            if fd >= 0 && hook(fd) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+        slot
+    }
+
+    pub fn set(&self, fd: RawFd) {
+        self.fd.store(fd, Ordering::SeqCst);
+    }
 
-use self::libc::c_int;
-use self::may_signal::unix::Signal;
+    // set the fd for the next child only, it's reset once the guard drops
+    #[cfg(target_os = "linux")]
+    pub fn arm(&self, fd: RawFd) -> ArmedFd {
+        self.set(fd);
+        ArmedFd { slot: self.clone() }
+    }
+}
+
+// resets a `PreExecFd` once the child is spawned
+#[cfg(target_os = "linux")]
+pub struct ArmedFd {
+    slot: PreExecFd,
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for ArmedFd {
+    fn drop(&mut self) {
+        self.slot.set(-1);
+    }
+}
+
+// redirects one of stdout and stderr of the child into the other one,
+// which is done by a `dup2` right before executing the program, after
+// the stdio of the child is set up
+pub struct Redirect {
+    // the fd that the other one of stdout and stderr is pointed to
+    from: PreExecFd,
+}
+
+impl Redirect {
+    // point the other one of stdout and stderr to `from`, which is 1 or 2
+    pub fn set(redirect: &mut Option<Redirect>, cmd: &mut process::Command, from: libc::c_int) {
+        if let Some(ref r) = *redirect {
+            r.from.set(from);
+            return;
+        }
+
+        let from = unsafe { PreExecFd::new(cmd, from, |from| libc::dup2(from, 3 - from)) };
+        *redirect = Some(Redirect { from });
+    }
+}
+
+/// Resource usage of a child process and all of its waited-for
+/// descendants, as reported by `wait4`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ResourceUsage {
+    /// CPU time spent in user mode.
+    pub user_time: Duration,
+    /// CPU time spent in kernel mode.
+    pub system_time: Duration,
+    /// Peak resident set size in bytes.
+    pub max_rss: u64,
+    /// Page faults serviced without any I/O.
+    pub minor_faults: u64,
+    /// Page faults that required I/O.
+    pub major_faults: u64,
+    /// Context switches because the process waited for a resource.
+    pub voluntary_context_switches: u64,
+    /// Context switches because the process was preempted.
+    pub involuntary_context_switches: u64,
+}
+
+impl ResourceUsage {
+    fn from_raw(ru: &libc::rusage) -> ResourceUsage {
+        let time = |t: libc::timeval| {
+            Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+        };
+        // macOS reports the rss in bytes, others in kilobytes
+        let rss_unit = if cfg!(any(target_os = "macos", target_os = "ios")) {
+            1
+        } else {
+            1024
+        };
+        ResourceUsage {
+            user_time: time(ru.ru_utime),
+            system_time: time(ru.ru_stime),
+            max_rss: ru.ru_maxrss as u64 * rss_unit,
+            minor_faults: ru.ru_minflt as u64,
+            major_faults: ru.ru_majflt as u64,
+            voluntary_context_switches: ru.ru_nvcsw as u64,
+            involuntary_context_switches: ru.ru_nivcsw as u64,
+        }
+    }
+}
+
+// the exit status and the resource usage of a reaped child
+type Exit = (ExitStatus, ResourceUsage);
 
 pub struct Child {
-    pub child: process::Child,
-    sigchld: Signal,
+    child: process::Child,
+    reaped: Receiver<io::Result<Exit>>,
+    // the exit of the child once it's reaped
+    exit: Option<Exit>,
 }
 
 impl fmt::Debug for Child {
@@ -39,10 +303,19 @@ impl fmt::Debug for Child {
 }
 
 impl Child {
-    pub fn new(child: process::Child) -> Child {
-        Child {
-            child: child,
-            sigchld: Signal::new(libc::SIGCHLD).expect("can't create signal stream"),
+    pub fn new(mut child: process::Child) -> io::Result<Child> {
+        match reaper::register(&child) {
+            Ok(reaped) => Ok(Child {
+                child,
+                reaped,
+                exit: None,
+            }),
+            Err(e) => {
+                // nobody would reap the child, so don't leave it behind
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(e)
+            }
         }
     }
 
@@ -51,53 +324,300 @@ impl Child {
     }
 
     pub fn kill(&mut self) -> io::Result<()> {
-        self.child.kill()
+        self.signal(Signal::Kill)
+    }
+
+    pub fn signal(&mut self, sig: Signal) -> io::Result<()> {
+        // the pid may be recycled once the child is reaped
+        if self.exit.is_some() {
+            return Ok(());
+        }
+        reaper::signal(self.id() as libc::pid_t, sig.as_raw())
+    }
+
+    // send `token` to `tx` once the child is reaped
+    pub fn notify(&mut self, tx: Sender<usize>, token: usize) {
+        if self.exit.is_some() || !reaper::notify(self.id() as libc::pid_t, &tx, token) {
+            // already reaped, the exit status is ready
+            let _ = tx.send(token);
+        }
     }
 
     // this is blocking API
     pub fn wait(&mut self) -> io::Result<ExitStatus> {
-        drop(self.child.stdin.take());
+        self.wait_with_rusage().map(|(status, _)| status)
+    }
+
+    // this is blocking API
+    pub fn wait_with_rusage(&mut self) -> io::Result<Exit> {
+        if let Some(exit) = self.exit {
+            return Ok(exit);
+        }
+        let exit = self.reaped.recv().map_err(|_| unwatched())??;
+        self.exit = Some(exit);
+        Ok(exit)
+    }
+
+    // this is blocking API
+    pub fn wait_deadline(&mut self, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+        if self.exit.is_none() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            self.exit = match self.reaped.recv_timeout(timeout) {
+                Ok(ret) => Some(ret?),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return Err(unwatched()),
+            };
+        }
+        Ok(self.exit.map(|(status, _)| status))
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        if self.exit.is_none() {
+            self.exit = match self.reaped.try_recv() {
+                Ok(ret) => Some(ret?),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Err(unwatched()),
+            };
+        }
+        Ok(self.exit.map(|(status, _)| status))
+    }
+}
+
+fn unwatched() -> io::Error {
+    io::Error::other("the child is not watched by the reaper")
+}
+
+// set by `force_sigchld_reaper`
+#[cfg(target_os = "linux")]
+static SIGCHLD_FORCED: AtomicBool = AtomicBool::new(false);
+
+/// Reaps the children spawned from now on through SIGCHLD instead of their
+/// pidfds, so that the fallback reaper can be tested where pidfd is
+/// supported. This is not a stable API, it's only meant for the tests.
+#[doc(hidden)]
+#[cfg(target_os = "linux")]
+pub fn force_sigchld_reaper() {
+    SIGCHLD_FORCED.store(true, Ordering::SeqCst);
+}
+
+mod reaper {
+    use std::collections::HashMap;
+    use std::io;
+    use std::os::unix::prelude::*;
+    use std::process::{self, ExitStatus};
+    use std::sync::{mpsc as std_mpsc, Mutex, MutexGuard, OnceLock};
+    use std::thread;
+
+    use super::may_signal::unix::Signal;
+    use super::{libc, Exit, ResourceUsage};
+    use may::sync::mpsc::{channel, Receiver, Sender};
+
+    struct Entry {
+        tx: Sender<io::Result<Exit>>,
+        // extra notification of the exit, used by `ChildSet`
+        notify: Option<(Sender<usize>, usize)>,
+        // the pidfd of the child, it's owned by the coroutine that watches it
+        // and kept open until the child is reaped
+        pidfd: Option<RawFd>,
+    }
+
+    type Children = HashMap<libc::pid_t, Entry>;
+
+    // all the children that are not reaped yet
+    fn children() -> MutexGuard<'static, Children> {
+        static CHILDREN: OnceLock<Mutex<Children>> = OnceLock::new();
+        CHILDREN
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    // register the child to the reaper, the returned channel
+    // receives the exit status once the child is reaped
+    pub fn register(child: &process::Child) -> io::Result<Receiver<io::Result<Exit>>> {
+        let pid = child.id() as libc::pid_t;
+        let (tx, rx) = channel();
 
-        loop {
-            // try wait first
-            if let Some(e) = self.try_wait()? {
-                return Ok(e);
+        #[cfg(target_os = "linux")]
+        if !sigchld::forced() {
+            // the child is not reaped yet, so the pid can't be recycled
+            if let Some(io) = pidfd::open(pid) {
+                let entry = Entry {
+                    tx,
+                    notify: None,
+                    pidfd: Some(io.inner().as_raw_fd()),
+                };
+                children().insert(pid, entry);
+                pidfd::watch(io, pid);
+                return Ok(rx);
             }
+        }
 
-            match self.sigchld.recv() {
-                Ok(_) => {
-                    // clear all the remaining signals
-                    while let Ok(_) = self.sigchld.try_recv() {}
-                    // the signal may be other child exist signal
-                    // so we need to check again in the loop
-                    continue;
-                }
-                Err(e) => {
-                    let msg = format!("failed to recv signal, err={}", e);
-                    return Err(io::Error::new(io::ErrorKind::Other, msg));
-                }
+        sigchld::start()?;
+        let mut children = children();
+        let entry = Entry {
+            tx,
+            notify: None,
+            pidfd: None,
+        };
+        children.insert(pid, entry);
+        // the child may exit before the reaper is watching it
+        try_reap(&mut children, pid);
+        Ok(rx)
+    }
+
+    // also notify `tx` when the child is reaped,
+    // returns false if the child is reaped already
+    pub fn notify(pid: libc::pid_t, tx: &Sender<usize>, token: usize) -> bool {
+        match children().get_mut(&pid) {
+            Some(entry) => {
+                entry.notify = Some((tx.clone(), token));
+                true
             }
+            None => false,
+        }
+    }
+
+    // send a signal to the child if it's not reaped yet,
+    // the lock makes sure that the pid is not recycled meanwhile
+    pub fn signal(pid: libc::pid_t, sig: libc::c_int) -> io::Result<()> {
+        let children = children();
+        let rc = match children.get(&pid) {
+            // the child is reaped, but the `Child` doesn't know it yet
+            None => return Ok(()),
+            #[cfg(target_os = "linux")]
+            Some(Entry {
+                pidfd: Some(fd), ..
+            }) => unsafe {
+                libc::syscall(
+                    libc::SYS_pidfd_send_signal,
+                    *fd,
+                    sig,
+                    std::ptr::null::<libc::siginfo_t>(),
+                    0,
+                ) as libc::c_int
+            },
+            Some(_) => unsafe { libc::kill(pid, sig) },
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
         }
+        Ok(())
     }
 
-    pub fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
-        let id = self.id() as c_int;
+    // reap the child if it has exited and send out the exit status,
+    // returns false if the child is still running
+    fn try_reap(children: &mut Children, pid: libc::pid_t) -> bool {
         let mut status = 0;
-        loop {
-            match unsafe { libc::waitpid(id, &mut status, libc::WNOHANG) } {
-                0 => return Ok(None),
+        let mut rusage = unsafe { std::mem::zeroed() };
+        let ret = loop {
+            match unsafe { libc::wait4(pid, &mut status, libc::WNOHANG, &mut rusage) } {
+                0 => return false,
                 n if n < 0 => {
                     let err = io::Error::last_os_error();
                     if err.kind() == io::ErrorKind::Interrupted {
                         continue;
                     }
-                    return Err(err);
+                    break Err(err);
                 }
                 n => {
-                    assert_eq!(n, id);
-                    return Ok(Some(ExitStatus::from_raw(status)));
+                    assert_eq!(n, pid);
+                    let usage = ResourceUsage::from_raw(&rusage);
+                    break Ok((ExitStatus::from_raw(status), usage));
                 }
             }
+        };
+
+        if let Some(entry) = children.remove(&pid) {
+            // the `Child` may be dropped already
+            let _ = entry.tx.send(ret);
+            if let Some((tx, token)) = entry.notify {
+                let _ = tx.send(token);
+            }
+        }
+        true
+    }
+
+    mod sigchld {
+        use super::*;
+
+        // whether this reaper is used where pidfd is supported
+        #[cfg(target_os = "linux")]
+        pub fn forced() -> bool {
+            super::super::SIGCHLD_FORCED.load(std::sync::atomic::Ordering::SeqCst)
+        }
+
+        // spawn the reaper thread that owns the only SIGCHLD stream
+        pub fn start() -> io::Result<()> {
+            static STARTED: OnceLock<Result<(), String>> = OnceLock::new();
+            STARTED
+                .get_or_init(|| {
+                    let (tx, rx) = std_mpsc::channel();
+                    thread::Builder::new()
+                        .name("may_process_reaper".into())
+                        .spawn(move || match Signal::new(libc::SIGCHLD) {
+                            Ok(sigchld) => {
+                                let _ = tx.send(Ok(()));
+                                run(sigchld);
+                            }
+                            Err(e) => {
+                                let msg = format!("can't create signal stream, err={}", e);
+                                let _ = tx.send(Err(msg));
+                            }
+                        })
+                        .map_err(|e| e.to_string())?;
+                    rx.recv().map_err(|e| e.to_string())?
+                })
+                .clone()
+                .map_err(io::Error::other)
+        }
+
+        fn run(sigchld: Signal) {
+            while sigchld.recv().is_ok() {
+                // clear all the remaining signals
+                while sigchld.try_recv().is_ok() {}
+
+                // the signal may be other child exist signal
+                // so we need to check all the children
+                let mut children = children();
+                let pids: Vec<_> = children
+                    .iter()
+                    .filter(|(_, entry)| entry.pidfd.is_none())
+                    .map(|(pid, _)| *pid)
+                    .collect();
+                for pid in pids {
+                    try_reap(&mut children, pid);
+                }
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    mod pidfd {
+        use super::*;
+        use may::io::{CoIo, WaitIo};
+
+        // open a pidfd for the process and register it to the event loop,
+        // `None` if pidfd is not supported
+        pub fn open(pid: libc::pid_t) -> Option<CoIo<OwnedFd>> {
+            let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+            if fd < 0 {
+                return None;
+            }
+            let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+            CoIo::new(fd).ok()
+        }
+
+        // reap the child in a new coroutine once its pidfd is readable,
+        // the pidfd is closed after that
+        pub fn watch(io: CoIo<OwnedFd>, pid: libc::pid_t) {
+            may::go!(move || loop {
+                io.reset_io();
+                if try_reap(&mut children(), pid) {
+                    break;
+                }
+                io.wait_io();
+            });
         }
     }
 }
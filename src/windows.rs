@@ -20,6 +20,7 @@ use std::os::windows::prelude::*;
 use std::os::windows::process::ExitStatusExt;
 use std::process::{self, ExitStatus};
 use std::sync::Arc;
+use std::time::Instant;
 
 use self::winapi::shared::minwindef::*;
 use self::winapi::shared::winerror::*;
@@ -34,6 +35,8 @@ use may::sync::Blocker;
 struct Waiter {
     wait_object: HANDLE,
     blocker: Arc<Blocker>,
+    // the reference passed to the callback
+    ptr: *const Blocker,
 }
 
 unsafe impl Sync for Waiter {}
@@ -42,16 +45,21 @@ unsafe impl Send for Waiter {}
 impl Drop for Waiter {
     fn drop(&mut self) {
         unsafe {
+            // this waits for a running callback to complete
             let rc = UnregisterWaitEx(self.wait_object, INVALID_HANDLE_VALUE);
             if rc == 0 {
+                // the callback may still run, so the reference is leaked
                 eprintln!("failed to unregister: {}", io::Error::last_os_error());
+                return;
             }
+            // the callback only borrows the reference, whether it ran or not
+            drop(Arc::from_raw(self.ptr));
         }
     }
 }
 
 pub struct Child {
-    pub child: process::Child,
+    child: process::Child,
 }
 
 impl fmt::Debug for Child {
@@ -61,8 +69,8 @@ impl fmt::Debug for Child {
 }
 
 impl Child {
-    pub fn new(child: process::Child) -> Child {
-        Child { child: child }
+    pub fn new(child: process::Child) -> io::Result<Child> {
+        Ok(Child { child })
     }
 
     pub fn id(&self) -> u32 {
@@ -96,13 +104,12 @@ impl Child {
         Ok(Waiter {
             blocker,
             wait_object,
+            ptr,
         })
     }
 
     // this is blocking API
     pub fn wait(&mut self) -> io::Result<ExitStatus> {
-        drop(self.child.stdin.take());
-
         // try wait first
         if let Some(e) = self.try_wait()? {
             return Ok(e);
@@ -119,6 +126,27 @@ impl Child {
             .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "can't get exitstatus"))
     }
 
+    // this is blocking API
+    pub fn wait_deadline(&mut self, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+        // try wait first
+        if let Some(e) = self.try_wait()? {
+            return Ok(Some(e));
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+
+        // register the waiter
+        let waiter = self.register()?;
+
+        // wait for the completion or the timeout
+        waiter.blocker.park(Some(deadline - now)).ok();
+
+        self.try_wait()
+    }
+
     pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
         unsafe {
             match WaitForSingleObject(self.child.as_raw_handle(), 0) {
@@ -137,7 +165,8 @@ impl Child {
     }
 }
 
+// the reference is released by the `Waiter`, which outlives the callback
 unsafe extern "system" fn callback(ptr: PVOID, _timer_fired: BOOLEAN) {
-    let blocker = Arc::from_raw(ptr as *mut Blocker);
+    let blocker = &*(ptr as *const Blocker);
     blocker.unpark();
 }
@@ -4,7 +4,14 @@
 extern crate may;
 extern crate may_process;
 
-use may_process::Command;
+use std::io::{self, Read, Write};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use may_process::{
+    ChildSet, Command, Overflow, Pipeline, ProcessGroup, ProcessPool, RestartPolicy, Signal,
+    Stream, Supervisor, SupervisorEvent, Termination,
+};
 
 #[test]
 fn simple_test() {
@@ -65,3 +72,1164 @@ fn coroutine_test() {
         }
     );
 }
+
+#[test]
+fn coroutine_stdio() {
+    go!(|| {
+        let mut child = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to execute process");
+
+        let mut stdin = child.take_stdin().unwrap();
+        stdin.write_all(b"hello may").unwrap();
+        drop(stdin);
+
+        let mut out = String::new();
+        child.stdout().unwrap().read_to_string(&mut out).unwrap();
+        assert_eq!(out, "hello may");
+        assert!(child.stderr().is_none());
+
+        let exit_status = child.wait().unwrap();
+        assert!(exit_status.success());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn large_output() {
+    // stderr is filled first, a reader that only drains stdout
+    // before stderr would dead lock here
+    const SIZE: usize = 4 * 1024 * 1024;
+    let script = format!("head -c {0} /dev/zero >&2; head -c {0} /dev/zero", SIZE);
+    let ret = go!(move || Command::new("sh").args(["-c", &script]).output())
+        .join()
+        .expect("something wrong")
+        .expect("failed to execute process");
+    assert!(ret.status.success());
+    assert_eq!(ret.stdout.len(), SIZE);
+    assert_eq!(ret.stderr.len(), SIZE);
+}
+
+#[test]
+fn wait_timeout() {
+    go!(|| {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let ret = child.wait_timeout(Duration::from_millis(200)).unwrap();
+        assert!(ret.is_none());
+        child.kill().unwrap();
+        let ret = child.wait_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!ret.unwrap().success());
+
+        let mut child = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+        let ret = child.wait_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(ret.unwrap().code(), Some(3));
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn command_timeout() {
+    let start = Instant::now();
+    let err = Command::new("sleep")
+        .arg("10")
+        .status_timeout(Duration::from_millis(200))
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    let err = Command::new("sleep")
+        .arg("10")
+        .output_timeout(Duration::from_millis(200))
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let ret = Command::new("sh")
+        .args(["-c", "echo hello"])
+        .output_timeout(Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(ret.stdout, b"hello\n");
+}
+
+#[test]
+fn many_children_wait() {
+    reaper_backend();
+    let handles: Vec<_> = (0..20)
+        .map(|i| {
+            go!(move || {
+                let script = format!("sleep 0.{}; exit {}", i % 5, i);
+                let status = Command::new("sh").args(["-c", &script]).status();
+                assert_eq!(status.unwrap().code(), Some(i));
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().expect("something wrong");
+    }
+}
+
+// the reaper tests run by `sigchld_reaper` force the SIGCHLD reaper,
+// returns whether it's forced
+fn reaper_backend() -> bool {
+    #[cfg(target_os = "linux")]
+    if std::env::var_os("MAY_PROCESS_TEST_SIGCHLD").is_some() {
+        may_process::force_sigchld_reaper();
+        return true;
+    }
+    false
+}
+
+// the fds of the pidfds of the current process referring to `pid`
+#[cfg(target_os = "linux")]
+fn pidfds_of(pid: u32) -> Vec<String> {
+    let line = format!("Pid:\t{}", pid);
+    std::fs::read_dir("/proc/self/fdinfo")
+        .unwrap()
+        .filter_map(|e| {
+            let path = e.ok()?.path();
+            let info = std::fs::read_to_string(&path).ok()?;
+            let fd = path.file_name()?.to_str()?.to_owned();
+            info.lines().any(|l| l == line).then_some(fd)
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn pidfd_reaper() {
+    let supported = unsafe { libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0) };
+    if supported < 0 {
+        eprintln!("pidfd is not supported, skipped");
+        return;
+    }
+    unsafe { libc::close(supported as libc::c_int) };
+
+    go!(|| {
+        let mut long = Command::new("sleep").arg("10").spawn().unwrap();
+        let mut short = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+        // each child is watched through its own pidfd, unless the
+        // SIGCHLD reaper is forced by `sigchld_reaper`
+        let forced = reaper_backend();
+        assert_eq!(pidfds_of(long.id()).len(), if forced { 0 } else { 1 });
+
+        // the exit of the other child doesn't wake up the waiter
+        let start = Instant::now();
+        assert!(long
+            .wait_timeout(Duration::from_millis(300))
+            .unwrap()
+            .is_none());
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert_eq!(short.wait().unwrap().code(), Some(3));
+        assert!(pidfds_of(short.id()).is_empty());
+
+        long.kill().unwrap();
+        long.wait().unwrap();
+        // the pidfd is closed once the child is reaped
+        assert!(pidfds_of(long.id()).is_empty());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+// run the reaper tests again with the SIGCHLD reaper, which is only used
+// where pidfd is not supported otherwise
+#[cfg(target_os = "linux")]
+#[test]
+fn sigchld_reaper() {
+    if std::env::var_os("MAY_PROCESS_TEST_SIGCHLD").is_some() {
+        return;
+    }
+    let tests = [
+        "wait_exited_child",
+        "many_children_wait",
+        "cached_exit_status",
+        "pidfd_reaper",
+    ];
+    let output = Command::new(std::env::current_exe().unwrap())
+        .env("MAY_PROCESS_TEST_SIGCHLD", "1")
+        .args(["--exact", "--test-threads", "1"])
+        .args(tests)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("test result: ok. 4 passed"), "{}", stdout);
+}
+
+#[test]
+fn wait_exited_child() {
+    reaper_backend();
+    go!(|| {
+        let mut child = Command::new("sh").args(["-c", "exit 7"]).spawn().unwrap();
+        // the child is reaped in the background meanwhile
+        may::coroutine::sleep(Duration::from_millis(200));
+        assert_eq!(child.wait().unwrap().code(), Some(7));
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn cached_exit_status() {
+    reaper_backend();
+    go!(|| {
+        let mut child = Command::new("sh").args(["-c", "exit 5"]).spawn().unwrap();
+        assert_eq!(child.wait().unwrap().code(), Some(5));
+        assert_eq!(child.wait().unwrap().code(), Some(5));
+        assert_eq!(child.try_wait().unwrap().unwrap().code(), Some(5));
+        // the child is reaped, kill is a no-op now
+        child.kill().unwrap();
+
+        let mut child = Command::new("sh").args(["-c", "exit 6"]).spawn().unwrap();
+        while child.try_wait().unwrap().is_none() {
+            may::coroutine::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(child.try_wait().unwrap().unwrap().code(), Some(6));
+        assert_eq!(child.wait().unwrap().code(), Some(6));
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn signal_child() {
+    use std::os::unix::process::ExitStatusExt;
+
+    go!(|| {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        child.signal(Signal::Stop).unwrap();
+        child.signal(Signal::Cont).unwrap();
+        assert!(child.try_wait().unwrap().is_none());
+        child.signal(Signal::Term).unwrap();
+        let status = child.wait().unwrap();
+        assert_eq!(status.signal(), Some(Signal::Term.as_raw()));
+        // the child is reaped, no signal is sent any more
+        child.signal(Signal::Usr1).unwrap();
+
+        assert_eq!(Signal::from_raw(libc::SIGTERM), Signal::Term);
+        let alarm = Signal::from_raw(libc::SIGALRM);
+        assert_eq!(alarm, Signal::Other(libc::SIGALRM));
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        child.signal(alarm).unwrap();
+        let status = child.wait().unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGALRM));
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn terminate_child() {
+    use std::os::unix::process::ExitStatusExt;
+
+    go!(|| {
+        let script = "trap 'exit 3' TERM; while true; do sleep 0.1; done";
+        let mut child = Command::new("sh").args(["-c", script]).spawn().unwrap();
+        // give the shell some time to install the trap
+        may::coroutine::sleep(Duration::from_millis(200));
+        let termination = child.terminate(Duration::from_secs(5)).unwrap();
+        assert!(matches!(termination, Termination::Graceful(s) if s.code() == Some(3)));
+
+        // killed by itself, not by the escalation
+        let script = "trap 'kill -9 $$' TERM; while true; do sleep 0.1; done";
+        let mut child = Command::new("sh").args(["-c", script]).spawn().unwrap();
+        may::coroutine::sleep(Duration::from_millis(200));
+        let termination = child.terminate(Duration::from_secs(5)).unwrap();
+        assert!(!termination.killed());
+        assert_eq!(termination.status().signal(), Some(Signal::Kill.as_raw()));
+
+        let script = "trap '' TERM; exec sleep 10";
+        let mut child = Command::new("sh").args(["-c", script]).spawn().unwrap();
+        may::coroutine::sleep(Duration::from_millis(200));
+        let start = Instant::now();
+        let termination = child.terminate(Duration::from_millis(300)).unwrap();
+        assert!(matches!(termination, Termination::Killed(_)));
+        assert_eq!(termination.status().signal(), Some(Signal::Kill.as_raw()));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    })
+    .join()
+    .expect("something wrong");
+}
+
+// wait until the process is gone, it must not be left as a zombie
+#[cfg(target_os = "linux")]
+fn process_gone(pid: u32) -> bool {
+    let path = format!("/proc/{}", pid);
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        if !std::path::Path::new(&path).exists() {
+            return true;
+        }
+        may::coroutine::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn kill_on_drop() {
+    go!(|| {
+        let child = Command::new("sleep")
+            .arg("10")
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        drop(child);
+        assert!(process_gone(pid));
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn cancel_wait() {
+    use may::sync::mpsc::channel;
+
+    for &kill in &[true, false] {
+        let (tx, rx) = channel();
+        let h = go!(move || {
+            let mut child = Command::new("sleep")
+                .arg("1")
+                .kill_on_cancel(kill)
+                .spawn()
+                .unwrap();
+            tx.send(child.id()).unwrap();
+            child.wait()
+        });
+        let pid = rx.recv().unwrap();
+        may::coroutine::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        unsafe { h.coroutine().cancel() };
+        assert!(h.join().is_err());
+        // the child is reaped in the background either way
+        assert!(process_gone(pid));
+        assert_eq!(start.elapsed() < Duration::from_millis(500), kill);
+    }
+}
+
+// a process is dead if it's gone or left as a zombie
+#[cfg(target_os = "linux")]
+fn process_dead(pid: u32) -> bool {
+    let path = format!("/proc/{}/stat", pid);
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        match std::fs::read_to_string(&path) {
+            Ok(stat) if !stat.contains(") Z ") => {}
+            _ => return true,
+        }
+        may::coroutine::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn kill_process_group() {
+    go!(|| {
+        let mut child = Command::new("sh")
+            .args(["-c", "sleep 10 & echo $!; sleep 10"])
+            .stdout(Stdio::piped())
+            .process_group(ProcessGroup::New)
+            .spawn()
+            .unwrap();
+        assert_eq!(child.process_group_id(), Some(child.id()));
+
+        let mut line = [0; 32];
+        let n = child.stdout().unwrap().read(&mut line).unwrap();
+        let grandchild: u32 = std::str::from_utf8(&line[..n])
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+
+        child.kill_group().unwrap();
+        assert!(!child.wait().unwrap().success());
+        assert!(process_dead(grandchild));
+
+        let mut child = Command::new("true").spawn().unwrap();
+        let err = child.kill_group().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        child.wait().unwrap();
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "cgroup"))]
+fn cgroup_containment() {
+    use may_process::Cgroup;
+
+    let group = match Cgroup::new(&format!("may_process_test_{}", std::process::id())) {
+        Ok(group) => group,
+        Err(e) => {
+            eprintln!("skip cgroup test, no delegated cgroup v2: {}", e);
+            return;
+        }
+    };
+    let controllers = std::fs::read_to_string(group.path().join("cgroup.controllers")).unwrap();
+    if !controllers.split_whitespace().any(|c| c == "pids") {
+        eprintln!("skip cgroup test, the pids controller is not available");
+        return;
+    }
+    // the sh process is the only one allowed, so it can't fork
+    group.set_pids_max(Some(1)).unwrap();
+    let fork = || {
+        Command::new("sh")
+            .args(["-c", "/bin/true & wait"])
+            .stderr(Stdio::null())
+            .cgroup(&group)
+            .status()
+            .unwrap()
+    };
+    assert!(!fork().success());
+    group.set_pids_max(None).unwrap();
+    assert!(fork().success());
+
+    go!(move || {
+        let mut child = Command::new("sleep")
+            .arg("10")
+            .cgroup(&group)
+            .spawn()
+            .unwrap();
+        assert_eq!(group.procs().unwrap(), vec![child.id()]);
+        group.kill().unwrap();
+        assert!(!child.wait().unwrap().success());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "cgroup"))]
+fn fresh_cgroup() {
+    go!(|| {
+        let child = match Command::new("sleep").arg("10").fresh_cgroup().spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("skip fresh cgroup test, no writable cgroup v2: {}", e);
+                return;
+            }
+        };
+        let pid = child.id();
+        assert_eq!(child.cgroup().unwrap().procs().unwrap(), vec![pid]);
+        // the fresh cgroup is torn down with the child
+        drop(child);
+        assert!(process_gone(pid));
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn resource_usage() {
+    go!(|| {
+        // burn some cpu in the child
+        let script = "i=0; while [ $i -lt 100000 ]; do i=$((i+1)); done";
+        let mut child = Command::new("sh").args(["-c", script]).spawn().unwrap();
+        let (status, usage) = child.wait_with_rusage().unwrap();
+        assert!(status.success());
+        assert!(usage.user_time + usage.system_time > Duration::from_millis(1));
+        assert!(usage.max_rss > 0);
+        assert_eq!(child.wait_with_rusage().unwrap(), (status, usage));
+
+        let (output, usage) = Command::new("sh")
+            .args(["-c", "echo hello"])
+            .output_with_rusage()
+            .unwrap();
+        assert_eq!(output.stdout, b"hello\n");
+        assert!(usage.max_rss > 0);
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn wait_any_child() {
+    go!(|| {
+        let mut set = ChildSet::new();
+        for (key, secs) in [("slow", "0.6"), ("fast", "0.1"), ("mid", "0.3")] {
+            let child = Command::new("sleep").arg(secs).spawn().unwrap();
+            set.insert(key, child);
+        }
+        set.insert("fail", Command::new("false").spawn().unwrap());
+        assert_eq!(set.len(), 4);
+
+        let (key, status) = set.wait_any().unwrap().unwrap();
+        assert_eq!(key, "fail");
+        assert!(!status.success());
+
+        assert!(set
+            .wait_any_timeout(Duration::from_millis(10))
+            .unwrap()
+            .is_none());
+
+        let keys: Vec<_> = std::iter::from_fn(|| set.wait_any().unwrap())
+            .map(|(key, status)| {
+                assert!(status.success());
+                key
+            })
+            .collect();
+        assert_eq!(keys, ["fast", "mid", "slow"]);
+        assert!(set.is_empty());
+        assert!(set.wait_any().unwrap().is_none());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn pipeline() {
+    go!(|| {
+        let mut tr = Command::new("tr");
+        tr.args(["a-z", "A-Z"]);
+        let mut head = Command::new("head");
+        head.args(["-n", "2"]);
+
+        // the first stage would block forever without the pipe being closed
+        let output = Pipeline::new()
+            .pipe(Command::new("yes"))
+            .pipe(tr)
+            .pipe(head)
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, b"Y\nY\n");
+        assert_eq!(output.stderr.len(), 3);
+        let last = output.status.last();
+        assert!(last.success());
+        // `yes` is killed by SIGPIPE
+        assert!(!output.status.pipefail().success());
+        assert!(!output.status.success());
+
+        let mut echo = Command::new("echo");
+        echo.arg("hello");
+        let mut sh = Command::new("sh");
+        sh.args(["-c", "cat; echo oops >&2; exit 3"]);
+        let output = Pipeline::new()
+            .pipe(echo)
+            .pipe(sh)
+            .pipe(Command::new("cat"))
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, b"hello\n");
+        assert_eq!(output.stderr[1], b"oops\n");
+        assert_eq!(output.status.statuses()[1].code(), Some(3));
+        assert_eq!(output.status.pipefail().code(), Some(3));
+        assert!(output.status.last().success());
+
+        let status = Pipeline::new()
+            .pipe(Command::new("false"))
+            .status()
+            .unwrap();
+        assert!(!status.success());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn pipeline_output_limit() {
+    go!(|| {
+        let mut cat = Command::new("cat");
+        cat.max_output(1000, Overflow::Kill);
+        let output = Pipeline::new()
+            .pipe(Command::new("yes"))
+            .pipe(cat)
+            .output()
+            .unwrap();
+        assert!(output.truncated);
+        assert_eq!(output.stdout.len(), 1000);
+        // `yes` is killed by SIGPIPE once `cat` is gone
+        assert!(!output.status.statuses()[0].success());
+
+        let mut sh = Command::new("sh");
+        sh.args(["-c", "cat; yes >&2"])
+            .max_output(10, Overflow::Error);
+        let err = Pipeline::new()
+            .pipe(Command::new("true"))
+            .pipe(sh)
+            .output()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn pipeline_stdio_conflict() {
+    go!(|| {
+        let mut cat = Command::new("cat");
+        cat.input_from(&b"hello\n"[..]);
+        let output = Pipeline::new()
+            .pipe(cat)
+            .pipe(Command::new("cat"))
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, b"hello\n");
+
+        // the input would replace the pipe from the first stage
+        let mut cat = Command::new("cat");
+        cat.input_from(&b"hello\n"[..]);
+        let err = Pipeline::new()
+            .pipe(Command::new("true"))
+            .pipe(cat)
+            .spawn()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        #[cfg(target_os = "linux")]
+        {
+            let mut cat = Command::new("cat");
+            cat.pty(may_process::PtyConfig::new());
+            let err = Pipeline::new()
+                .pipe(Command::new("true"))
+                .pipe(cat)
+                .spawn()
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn checked_output() {
+    go!(|| {
+        let output = Command::new("echo").arg("ok").checked_output().unwrap();
+        assert_eq!(output.stdout, b"ok\n");
+        assert!(Command::new("true").checked_status().is_ok());
+
+        let err = Command::new("sh")
+            .args(["-c", "echo bad thing >&2; exit 7"])
+            .checked_output()
+            .unwrap_err();
+        assert_eq!(err.program(), "sh");
+        assert_eq!(err.args(), ["-c", "echo bad thing >&2; exit 7"]);
+        assert_eq!(err.code(), Some(7));
+        assert_eq!(err.stderr(), b"bad thing\n");
+        let msg = err.to_string();
+        assert!(msg.starts_with("`sh -c echo bad thing >&2; exit 7` failed with exit status: 7"));
+        assert!(msg.ends_with("stderr:\nbad thing"));
+
+        let err = Command::new("sh")
+            .args(["-c", "kill -9 $$"])
+            .checked_status()
+            .unwrap_err();
+        assert_eq!(err.signal(), Some(9));
+        assert!(err.stderr().is_empty());
+
+        let err = Command::new("/no/such/program")
+            .checked_output()
+            .unwrap_err();
+        assert!(err.status().is_none());
+        let err = io::Error::from(err);
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn max_output() {
+    go!(|| {
+        let script = "seq 1 100000; seq 1 10 >&2";
+
+        let output = Command::new("sh")
+            .args(["-c", script])
+            .max_output(6, Overflow::KeepHead)
+            .limited_output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(output.truncated);
+        assert_eq!(output.stdout, b"1\n2\n3\n");
+        assert_eq!(output.stderr, b"1\n2\n3\n");
+
+        let output = Command::new("sh")
+            .args(["-c", script])
+            .max_output(13, Overflow::KeepTail)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"99999\n100000\n");
+        assert_eq!(output.stderr, b"5\n6\n7\n8\n9\n10\n");
+
+        // within the limit
+        let output = Command::new("sh")
+            .args(["-c", "seq 1 10 >&2"])
+            .max_output(100, Overflow::Error)
+            .limited_output()
+            .unwrap();
+        assert!(!output.truncated);
+        assert_eq!(output.stderr.len(), 21);
+
+        let err = Command::new("yes")
+            .max_output(1 << 16, Overflow::Error)
+            .output()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+
+        let output = Command::new("yes")
+            .max_output(1 << 16, Overflow::Kill)
+            .limited_output()
+            .unwrap();
+        assert!(output.truncated);
+        assert_eq!(output.stdout.len(), 1 << 16);
+        assert!(!output.status.success());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn stream_lines() {
+    go!(|| {
+        let mut child = Command::new("sh")
+            .args(["-c", "echo one; sleep 0.2; printf 'two\\nthree'"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let start = Instant::now();
+        let mut lines = child.take_stdout().unwrap().lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "one");
+        // the first line is there before the child is done
+        assert!(start.elapsed() < Duration::from_millis(200));
+        let rest: Vec<_> = lines.map(Result::unwrap).collect();
+        assert_eq!(rest, ["two", "three"]);
+        assert!(child.wait().unwrap().success());
+
+        let mut child = Command::new("sh")
+            .args(["-c", "printf 'a\\0b c\\0' >&2"])
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let chunks: Vec<_> = child
+            .take_stderr()
+            .unwrap()
+            .split(0)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(chunks, [&b"a"[..], b"b c"]);
+        child.wait().unwrap();
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn output_interleaved() {
+    go!(|| {
+        let script = "echo a; sleep 0.05; echo b >&2; sleep 0.05; echo c; exit 1";
+        let output = Command::new("sh")
+            .args(["-c", script])
+            .output_interleaved()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+
+        let streams: Vec<_> = output.chunks.iter().map(|c| c.0).collect();
+        assert_eq!(streams, [Stream::Stdout, Stream::Stderr, Stream::Stdout]);
+        assert!(output.chunks.windows(2).all(|w| w[0].1 < w[1].1));
+        assert_eq!(output.combined(), b"a\nb\nc\n");
+        assert_eq!(output.stream(Stream::Stdout), b"a\nc\n");
+        assert_eq!(output.stream(Stream::Stderr), b"b\n");
+
+        let log = output.render();
+        let lines: Vec<_> = log.lines().map(|l| l.trim_start()).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("0.000000 out | a"));
+        assert!(lines[1].ends_with(" err | b"));
+        assert!(lines[2].ends_with(" out | c"));
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn output_interleaved_limit() {
+    use std::os::unix::process::ExitStatusExt;
+
+    go!(|| {
+        let output = Command::new("yes")
+            .max_output(1000, Overflow::Kill)
+            .output_interleaved()
+            .unwrap();
+        assert!(output.truncated);
+        assert_eq!(output.combined().len(), 1000);
+        assert_eq!(output.status.signal(), Some(Signal::Kill.as_raw()));
+
+        let err = Command::new("yes")
+            .max_output(1000, Overflow::Error)
+            .output_interleaved()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+
+        // the limit applies to both streams together
+        let script = "seq 1 5; sleep 0.1; seq 6 10 >&2";
+        let output = Command::new("sh")
+            .args(["-c", script])
+            .max_output(6, Overflow::KeepTail)
+            .output_interleaved()
+            .unwrap();
+        assert!(output.truncated);
+        assert!(output.status.success());
+        assert_eq!(output.combined(), b"\n9\n10\n");
+        assert!(output.stream(Stream::Stdout).is_empty());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn redirect_stderr() {
+    go!(|| {
+        let script = "echo out; echo err >&2; echo out";
+        let output = Command::new("sh")
+            .args(["-c", script])
+            .stderr_to_stdout()
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, b"out\nerr\nout\n");
+        assert!(output.stderr.is_empty());
+
+        let output = Command::new("sh")
+            .args(["-c", script])
+            .stderr_to_stdout()
+            .stdout_to_stderr()
+            .output()
+            .unwrap();
+        assert_eq!(output.stderr, b"out\nerr\nout\n");
+        assert!(output.stdout.is_empty());
+
+        // the merged stream can be streamed from the child as well
+        let mut child = Command::new("sh")
+            .args(["-c", script])
+            .stdout(Stdio::piped())
+            .stderr_to_stdout()
+            .spawn()
+            .unwrap();
+        let lines: Vec<_> = child
+            .take_stdout()
+            .unwrap()
+            .lines()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(lines, ["out", "err", "out"]);
+        assert!(child.wait().unwrap().success());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn output_with_input() {
+    go!(|| {
+        let output = Command::new("tr")
+            .args(["a-z", "A-Z"])
+            .output_with_input(b"hello\n")
+            .unwrap();
+        assert_eq!(output.stdout, b"HELLO\n");
+
+        // much more than the pipe buffers in both directions
+        let data: Vec<u8> = (0..4 << 20).map(|i| (i % 251) as u8).collect();
+        let output = Command::new("cat")
+            .input_from(io::Cursor::new(data.clone()))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(output.stdout == data);
+
+        // the child doesn't read the input
+        let output = Command::new("true")
+            .input_from(io::Cursor::new(data))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn pty_child() {
+    use may_process::PtyConfig;
+
+    go!(|| {
+        let script = "test -t 0 && test -t 1 && stty size; read line; stty size; echo got $line";
+        let mut child = Command::new("sh")
+            .args(["-c", script])
+            .pty(PtyConfig::new().size(30, 100))
+            .spawn()
+            .unwrap();
+        assert!(child.stdout().is_none());
+        let mut pty = child.take_pty().unwrap();
+
+        let mut buf = [0; 64];
+        let mut first = Vec::new();
+        while !first.ends_with(b"\r\n") {
+            let n = pty.read(&mut buf).unwrap();
+            first.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(first, b"30 100\r\n");
+
+        pty.resize(40, 120).unwrap();
+        pty.write_all(b"hi\n").unwrap();
+        let mut rest = String::new();
+        // reading ends once the child exits
+        pty.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "hi\r\n40 120\r\ngot hi\r\n");
+        assert!(child.wait().unwrap().success());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn pty_process_group() {
+    use may_process::PtyConfig;
+
+    let err = Command::new("true")
+        .pty(PtyConfig::new())
+        .process_group(ProcessGroup::New)
+        .spawn()
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[cfg(feature = "expect")]
+#[test]
+fn expect_session() {
+    use may_process::Session;
+
+    go!(|| {
+        let timeout = Duration::from_secs(5);
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "printf 'name? '; read n; echo \"hello $n\"; cat"]);
+        let mut session = Session::spawn(cmd).unwrap();
+
+        let m = session.expect("name? ", timeout).unwrap();
+        assert_eq!(m.before, b"");
+        session.send_line("may").unwrap();
+        let re = regex::bytes::Regex::new(r"hello \w+").unwrap();
+        let m = session.expect(re, timeout).unwrap();
+        assert_eq!(m.matched, b"hello may");
+
+        // nothing is written until there is more input
+        let start = Instant::now();
+        let err = session.expect("bye", Duration::from_millis(200));
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        session.send_line("bye").unwrap();
+        session.send_eof().unwrap();
+        let err = session.expect("never", timeout).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(session.expect_eof(timeout).unwrap(), b"\nbye\n");
+        assert!(session.wait().unwrap().success());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[cfg(all(target_os = "linux", feature = "expect"))]
+#[test]
+fn expect_session_pty() {
+    use may_process::{PtyConfig, Session};
+
+    go!(|| {
+        let timeout = Duration::from_secs(5);
+        let mut cmd = Command::new("sh");
+        cmd.args([
+            "-c",
+            "stty -echo; printf 'password: '; read p; echo \"[$p]\"",
+        ])
+        .pty(PtyConfig::new());
+        let mut session = Session::spawn(cmd).unwrap();
+
+        session.expect("password: ", timeout).unwrap();
+        session.send_line("secret").unwrap();
+        let m = session.expect("]", timeout).unwrap();
+        assert_eq!(m.before, b"[secret");
+        assert!(session.wait().unwrap().success());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn supervisor_restart() {
+    go!(|| {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "exit 3"]);
+        let handle = Supervisor::new(cmd)
+            .restart(RestartPolicy::OnFailure)
+            .max_restarts(2, Duration::from_secs(60))
+            .backoff(Duration::from_millis(10), Duration::from_millis(15))
+            .start();
+
+        // the channel is closed once the supervisor gives up
+        let events: Vec<_> = handle.events().iter().collect();
+        let delays: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                SupervisorEvent::Restarting(delay) => Some(delay.as_millis()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(delays, [10, 15]);
+        let exits = events
+            .iter()
+            .filter(|e| matches!(e, SupervisorEvent::Exited(s) if s.code() == Some(3)))
+            .count();
+        assert_eq!(exits, 3);
+        assert!(matches!(events[0], SupervisorEvent::Started(_)));
+        assert!(matches!(events.last(), Some(SupervisorEvent::GaveUp)));
+        assert_eq!(handle.stop().unwrap(), None);
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn supervisor_stop() {
+    use std::os::unix::process::ExitStatusExt;
+
+    go!(|| {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("10");
+        let handle = Supervisor::new(cmd).start();
+        let event = handle.events().recv().unwrap();
+        assert!(matches!(event, SupervisorEvent::Started(_)));
+
+        let start = Instant::now();
+        let termination = handle.stop().unwrap().unwrap();
+        assert!(!termination.killed());
+        assert_eq!(termination.status().signal(), Some(Signal::Term.as_raw()));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn process_pool() {
+    go!(|| {
+        let mut pool = ProcessPool::new(2);
+        for i in 0..6 {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", &format!("sleep 0.2; echo {}", i)]);
+            assert_eq!(pool.submit(cmd), i);
+        }
+        assert_eq!(pool.len(), 6);
+
+        let start = Instant::now();
+        let mut results: Vec<_> = pool.by_ref().collect();
+        let elapsed = start.elapsed();
+        // three rounds of two jobs
+        assert!(elapsed >= Duration::from_millis(600), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1200), "{:?}", elapsed);
+        assert!(pool.is_empty());
+
+        results.sort_by_key(|r| r.0);
+        for (i, (id, output)) in results.into_iter().enumerate() {
+            assert_eq!(id, i);
+            assert_eq!(output.unwrap().stdout, format!("{}\n", i).as_bytes());
+        }
+    })
+    .join()
+    .expect("something wrong");
+}
+
+// the pids written by the jobs of a pool, once all of them are written
+#[cfg(target_os = "linux")]
+fn job_pids(dir: &std::path::Path, jobs: usize) -> Vec<u32> {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        let pids: Vec<u32> = (0..jobs)
+            .filter_map(|i| std::fs::read_to_string(dir.join(i.to_string())).ok())
+            .filter_map(|pid| pid.trim().parse().ok())
+            .collect();
+        if pids.len() == jobs {
+            return pids;
+        }
+        may::coroutine::sleep(Duration::from_millis(10));
+    }
+    panic!("the jobs didn't start");
+}
+
+// a job writing its pid to `dir` before sleeping
+#[cfg(target_os = "linux")]
+fn pid_job(dir: &std::path::Path, job: usize) -> Command {
+    let mut cmd = Command::new("sh");
+    let script = format!("echo $$ > {}/{}; exec sleep 10", dir.display(), job);
+    cmd.args(["-c", &script]);
+    cmd
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn process_pool_cancel() {
+    go!(|| {
+        let dir = std::env::temp_dir().join(format!("may_process_pool_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut pool = ProcessPool::new(2);
+        for i in 0..4 {
+            pool.submit(pid_job(&dir, i));
+        }
+        let pids = job_pids(&dir, 2);
+
+        pool.cancel();
+        assert!(pool.is_empty());
+        assert!(pool.next().is_none());
+        // the running children are killed and reaped
+        for pid in pids {
+            assert!(process_gone(pid), "{} is left", pid);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn process_pool_fail_fast_cancel() {
+    go!(|| {
+        let dir = std::env::temp_dir().join(format!("may_process_fail_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut pool = ProcessPool::new(2);
+        pool.fail_fast(true);
+        pool.submit(pid_job(&dir, 0));
+        let mut fail = Command::new("sh");
+        fail.args(["-c", "sleep 0.5; exit 4"]);
+        pool.submit(fail);
+        for i in 2..6 {
+            pool.submit(pid_job(&dir, i));
+        }
+
+        // the failing job kills the others before its result is taken
+        let pid = job_pids(&dir, 1)[0];
+        assert!(process_gone(pid), "{} is left", pid);
+        assert!(!dir.join("2").exists());
+        // the cancellation is done, turning fail fast off doesn't undo it
+        pool.fail_fast(false);
+
+        let start = Instant::now();
+        let results: Vec<_> = pool.by_ref().collect();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+        assert_eq!(results[0].1.as_ref().unwrap().status.code(), Some(4));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(pool.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    })
+    .join()
+    .expect("something wrong");
+}
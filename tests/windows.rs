@@ -4,6 +4,10 @@
 extern crate may;
 extern crate may_process;
 
+use std::io::{self, Read};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
 use may_process::Command;
 
 #[test]
@@ -68,3 +72,67 @@ fn coroutine_test() {
         }
     );
 }
+
+#[test]
+fn coroutine_stdout() {
+    go!(|| {
+        let mut child = Command::new("cmd")
+            .args(&["/C", "echo hello"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to execute process");
+
+        let mut out = String::new();
+        child.stdout().unwrap().read_to_string(&mut out).unwrap();
+        assert_eq!(out.trim(), "hello");
+
+        let exit_status = child.wait().unwrap();
+        assert!(exit_status.success());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn wait_timeout() {
+    go!(|| {
+        let mut child = Command::new("cmd")
+            .args(&["/C", "ping -n 10 127.0.0.1 > nul"])
+            .spawn()
+            .unwrap();
+        let ret = child.wait_timeout(Duration::from_millis(200)).unwrap();
+        assert!(ret.is_none());
+        child.kill().unwrap();
+        let ret = child.wait_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!ret.unwrap().success());
+    })
+    .join()
+    .expect("something wrong");
+}
+
+#[test]
+fn command_timeout() {
+    go!(|| {
+        let start = Instant::now();
+        let err = Command::new("cmd")
+            .args(&["/C", "ping -n 10 127.0.0.1 > nul"])
+            .status_timeout(Duration::from_millis(200))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        let err = Command::new("cmd")
+            .args(&["/C", "ping -n 10 127.0.0.1 > nul"])
+            .output_timeout(Duration::from_millis(200))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let ret = Command::new("cmd")
+            .args(&["/C", "echo hello"])
+            .output_timeout(Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(ret.stdout, b"hello\r\n");
+    })
+    .join()
+    .expect("something wrong");
+}